@@ -0,0 +1,300 @@
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::process;
+
+use obs::config::EngineConfig;
+use obs::io::midi_reader::{MidiEvent, MidiEventKind, MidiFile};
+use obs::io::midi_stats::NoteStats;
+use obs::io::tempo_map::MidiTiming;
+use obs::{Error, Result};
+
+const USAGE: &str = "usage: info <file.mid> [--events] [--json]";
+
+/// major and minor keys by number of sharps, from 7 flats to 7 sharps
+const MAJOR_KEYS: [&str; 15] = ["Cb", "Gb", "Db", "Ab", "Eb", "Bb", "F", "C", "G", "D", "A", "E", "B", "F#", "C#"];
+const MINOR_KEYS: [&str; 15] = ["Ab", "Eb", "Bb", "F", "C", "G", "D", "A", "E", "B", "F#", "C#", "G#", "D#", "A#"];
+
+const NOTE_NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("info: {err}");
+        process::exit(1);
+    }
+}
+
+/// prints a summary of a midi file, with every event when `--events` is given,
+/// as json when `--json` is given
+fn run() -> Result<()> {
+    let mut path = None;
+    let mut events = false;
+    let mut json = false;
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--events" => events = true,
+            "--json" => json = true,
+            _ if arg.starts_with("--") || path.is_some() => return Err(Error::Usage(USAGE.to_string())),
+            _ => path = Some(arg),
+        }
+    }
+    let path = path.ok_or_else(|| Error::Usage(USAGE.to_string()))?;
+    let file = MidiFile::new(&fs::read(&path)?)?;
+
+    if json {
+        println!("{}", to_json(&path, &file, events));
+    } else {
+        print_summary(&path, &file);
+        if events {
+            print_events(&file);
+        }
+    }
+    Ok(())
+}
+
+/// scientific pitch name of midi key `key`, C4 being 60
+fn key2name(key: u8) -> String {
+    format!("{}{}", NOTE_NAMES[key as usize % 12], key as i32 / 12 - 1)
+}
+
+/// `sharps` sharps, or flats when negative, as the name of a key
+fn key_signature(sharps: i8, minor: bool) -> String {
+    let i = (sharps.clamp(-7, 7) + 7) as usize;
+    match minor {
+        false => format!("{} major", MAJOR_KEYS[i]),
+        true => format!("{} minor", MINOR_KEYS[i]),
+    }
+}
+
+/// `us` microseconds as minutes, seconds and milliseconds
+fn us2clock(us: u64) -> String {
+    let ms = us / 1000;
+    format!("{}:{:02}.{:03}", ms / 60_000, ms / 1000 % 60, ms % 1000)
+}
+
+fn timing(timing: MidiTiming) -> String {
+    match timing {
+        MidiTiming::Metrical(ticks_per_beat) => format!("{ticks_per_beat} ticks per beat"),
+        MidiTiming::Timecode { fps, ticks_per_frame } => {
+            format!("{} fps, {ticks_per_frame} ticks per frame", fps.as_f32())
+        }
+    }
+}
+
+fn notes(stats: &NoteStats) -> String {
+    match stats.range() {
+        Some((low, high)) => format!(
+            "{} notes, {} to {}, up to {} at once",
+            stats.notes, key2name(low), key2name(high), stats.polyphony,
+        ),
+        None => String::from("no notes"),
+    }
+}
+
+/// every event of the file along with the index of its track, in the order they play
+fn all_events(file: &MidiFile) -> Vec<(usize, &MidiEvent)> {
+    let mut events: Vec<(usize, &MidiEvent)> = file.tracks()
+        .iter()
+        .enumerate()
+        .flat_map(|(i, track)| track.events().iter().map(move |event| (i, event)))
+        .collect();
+    events.sort_by_key(|&(i, event)| (event.tick(), i));
+    events
+}
+
+fn print_summary(path: &str, file: &MidiFile) {
+    let stats = file.stats();
+    println!("{path}: {} tracks, {}, {}", stats.tracks.len(), timing(file.timing()), us2clock(stats.end_us));
+
+    if let MidiTiming::Metrical(_) = file.timing() {
+        println!("tempo:");
+        for change in file.tempo_map().changes() {
+            let bpm = 60_000_000. / change.tempo as f64;
+            println!("  {} (tick {}): {bpm:.2} bpm", us2clock(change.us), change.tick);
+        }
+    }
+
+    let mut signatures = vec![];
+    for (_, event) in all_events(file) {
+        let signature = match *event.kind() {
+            MidiEventKind::MetaTimeSignature { numerator, denominator, .. } => format!("{numerator}/{denominator}"),
+            MidiEventKind::MetaKeySignature { sharps, minor } => key_signature(sharps, minor),
+            _ => continue,
+        };
+        signatures.push(format!("  {} (tick {}): {signature}", us2clock(event.us()), event.tick()));
+    }
+    if !signatures.is_empty() {
+        println!("signatures:");
+        for signature in signatures {
+            println!("{signature}");
+        }
+    }
+
+    println!("tracks:");
+    for (i, track) in stats.tracks.iter().enumerate() {
+        let instrument = match track.instrument.as_str() {
+            "" => String::new(),
+            instrument => format!(" ({instrument})"),
+        };
+        let channels: Vec<String> = track.channels.iter().map(|channel| (channel + 1).to_string()).collect();
+        let channels = match channels.len() {
+            0 => String::new(),
+            _ => format!(" on channel {}", channels.join(", ")),
+        };
+        println!("  {i} '{}'{instrument}{channels}: {} events, {}", track.name, track.events, notes(&track.notes));
+    }
+
+    println!("channels:");
+    for channel in stats.channels.iter() {
+        println!("  {}: {}", channel.channel + 1, notes(&channel.notes));
+    }
+
+    let config = EngineConfig::default();
+    let fits = if stats.fits(&config) { "fits" } else { "doesn't fit" };
+    println!("{fits} {} channels of {} voices", config.channels_max, config.voices_max);
+}
+
+fn print_events(file: &MidiFile) {
+    println!("events:");
+    for (i, event) in all_events(file) {
+        let channel = match event.is_channel_event() {
+            true => format!(" channel {}", event.channel() + 1),
+            false => String::new(),
+        };
+        println!("  {} (tick {}) track {i}{channel}: {:?}", us2clock(event.us()), event.tick(), event.kind());
+    }
+}
+
+/// `s` as a json string
+fn json_str(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => { let _ = write!(out, "\\u{:04x}", c as u32); }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn json_notes(stats: &NoteStats) -> String {
+    let key = |key: Option<u8>| key.map_or(String::from("null"), |key| key.to_string());
+    format!(
+        "{{\"count\":{},\"lowest\":{},\"highest\":{},\"polyphony\":{},\"end_tick\":{},\"end_us\":{}}}",
+        stats.notes, key(stats.lowest), key(stats.highest), stats.polyphony, stats.end_tick, stats.end_us,
+    )
+}
+
+/// type and fields of an event as the members of a json object
+fn json_kind(kind: &MidiEventKind) -> String {
+    match kind {
+        MidiEventKind::NoteOff { key, velocity } => format!("\"type\":\"note_off\",\"key\":{key},\"velocity\":{velocity}"),
+        MidiEventKind::NoteOn { key, velocity } => format!("\"type\":\"note_on\",\"key\":{key},\"velocity\":{velocity}"),
+        MidiEventKind::PolyAftertouch { key, pressure } => {
+            format!("\"type\":\"poly_aftertouch\",\"key\":{key},\"pressure\":{pressure}")
+        }
+        MidiEventKind::ControlChange { controller, value } => {
+            format!("\"type\":\"control_change\",\"controller\":{controller},\"value\":{value}")
+        }
+        MidiEventKind::ProgramChange(program) => format!("\"type\":\"program_change\",\"program\":{program}"),
+        MidiEventKind::ChannelAftertouch(pressure) => format!("\"type\":\"channel_aftertouch\",\"pressure\":{pressure}"),
+        MidiEventKind::PitchBend(bend) => format!("\"type\":\"pitch_bend\",\"bend\":{bend}"),
+        MidiEventKind::SysEx(data) => {
+            let data: Vec<String> = data.iter().map(|byte| byte.to_string()).collect();
+            format!("\"type\":\"sysex\",\"data\":[{}]", data.join(","))
+        }
+        MidiEventKind::MetaSetTempo(tempo) => format!("\"type\":\"tempo\",\"tempo\":{tempo}"),
+        MidiEventKind::MetaTimeSignature { numerator, denominator, clocks_per_click, notes_per_quarter } => format!(
+            "\"type\":\"time_signature\",\"numerator\":{numerator},\"denominator\":{denominator},\
+             \"clocks_per_click\":{clocks_per_click},\"notes_per_quarter\":{notes_per_quarter}"
+        ),
+        MidiEventKind::MetaKeySignature { sharps, minor } => {
+            format!("\"type\":\"key_signature\",\"sharps\":{sharps},\"minor\":{minor}")
+        }
+        MidiEventKind::MetaText(text) => format!("\"type\":\"text\",\"text\":{}", json_str(text)),
+        MidiEventKind::MetaTrackName(text) => format!("\"type\":\"track_name\",\"text\":{}", json_str(text)),
+        MidiEventKind::MetaInstrumentName(text) => format!("\"type\":\"instrument_name\",\"text\":{}", json_str(text)),
+        MidiEventKind::MetaLyric(text) => format!("\"type\":\"lyric\",\"text\":{}", json_str(text)),
+        MidiEventKind::MetaMarker(text) => format!("\"type\":\"marker\",\"text\":{}", json_str(text)),
+        MidiEventKind::MetaEndOfTrack => String::from("\"type\":\"end_of_track\""),
+        MidiEventKind::Other => String::from("\"type\":\"other\""),
+    }
+}
+
+/// the summary, and every event when `events` is set, as a json object
+fn to_json(path: &str, file: &MidiFile, events: bool) -> String {
+    let stats = file.stats();
+    let mut out = String::from("{");
+
+    let _ = write!(out, "\"file\":{},", json_str(path));
+    let _ = match file.timing() {
+        MidiTiming::Metrical(ticks_per_beat) => {
+            write!(out, "\"timing\":{{\"type\":\"metrical\",\"ticks_per_beat\":{ticks_per_beat}}},")
+        }
+        MidiTiming::Timecode { fps, ticks_per_frame } => write!(
+            out,
+            "\"timing\":{{\"type\":\"timecode\",\"fps\":{},\"ticks_per_frame\":{ticks_per_frame}}},",
+            fps.as_f32(),
+        ),
+    };
+    let _ = write!(out, "\"end_tick\":{},\"end_us\":{},", stats.end_tick, stats.end_us);
+
+    let tempos: Vec<String> = file.tempo_map()
+        .changes()
+        .iter()
+        .map(|change| format!("{{\"tick\":{},\"us\":{},\"tempo\":{}}}", change.tick, change.us, change.tempo))
+        .collect();
+    let _ = write!(out, "\"tempo_map\":[{}],", tempos.join(","));
+
+    let tracks: Vec<String> = stats.tracks
+        .iter()
+        .map(|track| {
+            let channels: Vec<String> = track.channels.iter().map(|channel| channel.to_string()).collect();
+            format!(
+                "{{\"name\":{},\"instrument\":{},\"events\":{},\"channels\":[{}],\"end_tick\":{},\"end_us\":{},\"notes\":{}}}",
+                json_str(&track.name), json_str(&track.instrument), track.events, channels.join(","),
+                track.end_tick, track.end_us, json_notes(&track.notes),
+            )
+        })
+        .collect();
+    let _ = write!(out, "\"tracks\":[{}],", tracks.join(","));
+
+    let channels: Vec<String> = stats.channels
+        .iter()
+        .map(|channel| format!("{{\"channel\":{},\"notes\":{}}}", channel.channel, json_notes(&channel.notes)))
+        .collect();
+    let _ = write!(out, "\"channels\":[{}],", channels.join(","));
+
+    let config = EngineConfig::default();
+    let _ = write!(
+        out,
+        "\"fits\":{{\"channels_max\":{},\"voices_max\":{},\"fits\":{}}}",
+        config.channels_max, config.voices_max, stats.fits(&config),
+    );
+
+    if events {
+        let events: Vec<String> = all_events(file)
+            .into_iter()
+            .map(|(i, event)| {
+                let channel = match event.is_channel_event() {
+                    true => format!("\"channel\":{},", event.channel()),
+                    false => String::new(),
+                };
+                format!(
+                    "{{\"track\":{i},\"tick\":{},\"us\":{},\"delta_tick\":{},{channel}{}}}",
+                    event.tick(), event.us(), event.delta_tick(), json_kind(event.kind()),
+                )
+            })
+            .collect();
+        let _ = write!(out, ",\"events\":[{}]", events.join(","));
+    }
+
+    out.push('}');
+    out
+}
@@ -1,8 +1,22 @@
-use obs::{io::{audio_out::AudioOut, player::{Player, PlayerKind}}, synth::voice::Voice};
+use std::env;
+use std::process;
 
+use obs::config::EngineConfig;
+use obs::io::player::{KeyboardPlayer, Player};
+
+/// `keyboard [session.mid]` plays the synth from the computer keyboard, recording
+/// the session to a midi file if one is given
 fn main() {
-    let mut player = Player::new(PlayerKind::KeyboardPlayer);
-    player.keyboard_player();
-    let mut output = AudioOut::new();
-    let mut voice = Voice::new(440, 0.5);
-}
\ No newline at end of file
+    let config = EngineConfig::default();
+    let record = env::args().nth(1);
+    let result = KeyboardPlayer::new(&config)
+        .map(|keyboard| match record {
+            Some(path) => keyboard.record_to(path),
+            None => keyboard,
+        })
+        .and_then(|keyboard| Player::new(Box::new(keyboard)).keyboard_player());
+    if let Err(err) = result {
+        eprintln!("keyboard: {err}");
+        process::exit(1);
+    }
+}
@@ -1,27 +1,14 @@
-use obs::{io::player::{Player, PlayerKind}, synth::channel::NoiseChannel, AMPLITUDE_MAX};
+use std::process;
+
+use obs::config::EngineConfig;
+use obs::io::player::{KeyboardPlayer, Player};
 
 fn main() {
-    let mut noise = NoiseChannel::new(0.3);
-    let mut player = Player::new(PlayerKind::KeyboardPlayer);
-    let buffer = NoiseChannel::generate_brown_noise(300000, 0.005);
-    for sample in buffer {
-        // player.audio_out(sample == AMPLITUDE_MAX);
-        // dbg!(sample == AMPLITUDE_MAX);
+    let config = EngineConfig::default();
+    let result = KeyboardPlayer::new(&config)
+        .and_then(|keyboard| Player::new(Box::new(keyboard)).keyboard_player());
+    if let Err(err) = result {
+        eprintln!("main: {err}");
+        process::exit(1);
     }
-    player.drain();
-
-    // loop {
-    //     player.audio_out(noise.get_sample());
-    // }
-
-
-
-
-    // initialization
-
-    //while !end {
-        // if key_pressed {
-            // key_press()
-        // audio_out()
-        // update()
-}
\ No newline at end of file
+}
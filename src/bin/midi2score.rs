@@ -0,0 +1,39 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use obs::io::midi_reader::MidiFile;
+use obs::io::score_writer::{split_midi, write_score};
+use obs::{Error, Result};
+
+const USAGE: &str = "usage: midi2score <file.mid> [out_dir]";
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("midi2score: {err}");
+        process::exit(1);
+    }
+}
+
+/// writes one `<name>_t<track>_c<channel>_<voice>.txt` score per monophonic line of the file
+fn run() -> Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (path, out_dir) = match args.as_slice() {
+        [path] if !path.starts_with("--") => (PathBuf::from(path), PathBuf::from(".")),
+        [path, out_dir] if !path.starts_with("--") && !out_dir.starts_with("--") => {
+            (PathBuf::from(path), PathBuf::from(out_dir))
+        }
+        _ => return Err(Error::Usage(USAGE.to_string())),
+    };
+
+    let mut file = MidiFile::new(&fs::read(&path)?)?;
+    let name = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("score");
+
+    for line in split_midi(&mut file) {
+        let out = out_dir.join(format!("{name}_t{}_c{}_{}.txt", line.track, line.channel + 1, line.voice));
+        write_score(&out, &line.lines)?;
+        println!("{}: {} lines", out.display(), line.lines.len());
+    }
+    Ok(())
+}
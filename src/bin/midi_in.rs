@@ -0,0 +1,39 @@
+use std::env;
+use std::process;
+
+use alsa::seq::Addr;
+
+use obs::config::EngineConfig;
+use obs::io::midi_input::SeqInput;
+use obs::io::player::{MidiInputPlayer, Player};
+use obs::{Error, Result};
+
+const USAGE: &str = "usage: midi_in [client:port]";
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("midi_in: {err}");
+        process::exit(1);
+    }
+}
+
+/// plays whatever is sent to the `obs` sequencer port until Esc is pressed,
+/// connecting it to the port at `client:port` if one is given
+fn run() -> Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let sender = match args.as_slice() {
+        [] => None,
+        [addr] => Some(addr.parse::<Addr>().map_err(|_| Error::Usage(USAGE.to_string()))?),
+        _ => return Err(Error::Usage(USAGE.to_string())),
+    };
+
+    let input = SeqInput::new()?;
+    if let Some(sender) = sender {
+        input.connect(sender)?;
+    }
+    let addr = input.addr()?;
+    println!("listening on {}:{}, esc to quit", addr.client, addr.port);
+
+    let player = MidiInputPlayer::new(Box::new(input), &EngineConfig::default())?;
+    Player::new(Box::new(player)).play_live()
+}
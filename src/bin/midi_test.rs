@@ -1,29 +1,78 @@
-use alsa::Direction;
-use alsa::pcm::PCM;
-use midly::stream::Buffer;
-use obs::synth::drum_machine::{DrumMachine, DrumVoice};
-use obs::io::player::Player;
-use std::fs::File;
-use std::path::Path;
-use std::io::{BufReader, BufWriter, Write};
-use wav;
-
-use obs::synth::{drum_machine};
-use obs::{utils, io::midi_reader};
-use obs::io::wav_reader;
-use obs::SAMPLE_RATE;
-use obs::AMPLITUDE_MAX;
+use std::env;
+use std::fs;
+use std::process;
+use std::str::FromStr;
+
+use obs::config::EngineConfig;
+use obs::io::audio_out::AudioOut;
+use obs::io::midi_reader::MidiFile;
+use obs::io::player::{MidiPlayer, Player};
+use obs::io::scala_reader;
+use obs::io::wav_writer::WavSink;
+use obs::synth::channel::MixMode;
+use obs::synth::Synth;
+use obs::synth::tuning::{KeyboardMapping, Scale, Tuning};
+use obs::{Error, Result};
+
+const USAGE: &str = "usage: midi_test <file.mid> [out.wav] [--rate hz] [--scl scale.scl] [--kbm mapping.kbm] [--pin us]";
 
 fn main() {
-    let pcm = PCM::new("default", Direction::Playback, false).unwrap();
+    if let Err(err) = run() {
+        eprintln!("midi_test: {err}");
+        process::exit(1);
+    }
+}
+
+fn usage() -> Error {
+    Error::Usage(USAGE.to_string())
+}
+
+/// value of an option, a usage error when missing or malformed
+fn parse_arg<T: FromStr>(arg: Option<String>) -> Result<T> {
+    arg.and_then(|arg| arg.parse().ok()).ok_or_else(usage)
+}
+
+fn run() -> Result<()> {
+    let mut path = None;
+    let mut wav_path = None;
+    let mut scale = Scale::default();
+    let mut mapping = KeyboardMapping::default();
+    let mut config = EngineConfig::default();
+    let mut mix = MixMode::default();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--rate" => config.sample_rate = parse_arg(args.next())?,
+            "--scl" => scale = scala_reader::read_scl(args.next().ok_or_else(usage)?)?,
+            "--kbm" => mapping = scala_reader::read_kbm(args.next().ok_or_else(usage)?)?,
+            "--pin" => mix = MixMode::Pin { width: parse_arg(args.next())? },
+            _ if arg.starts_with("--") => return Err(usage()),
+            _ if path.is_none() => path = Some(arg),
+            _ if wav_path.is_none() => wav_path = Some(arg),
+            _ => return Err(usage()),
+        }
+    }
+
+    config.validate()?;
+
+    let file = MidiFile::new(&fs::read(path.ok_or_else(usage)?)?)?;
+
+    // render to a wav file if one is given, otherwise play on the sound card
+    let output = match wav_path {
+        Some(wav_path) => AudioOut::with_sink(Box::new(WavSink::new(wav_path, &config)?), &config),
+        None => AudioOut::new(&config)?,
+    };
+    if let Some(params) = output.params() {
+        println!("playing at {} Hz, {} samples per period", params.rate, params.period_size);
+    }
 
-    utils::set_pcm_params(&pcm);
+    let mut synth = Synth::new(output.config());
+    for channel_n in 0..config.channels_max {
+        synth.set_tuning(Tuning::new(scale.clone(), mapping.clone()), channel_n);
+        synth.set_mix(mix, channel_n);
+    }
 
-    let mut reader = midi_reader::MidiFile::new(include_bytes!("../../musicas/ice_cap.mid"));
-    reader.list_tracks();
-    
-    // In case the buffer was larger than 2 seconds, start the stream manually.
-    // if pcm.state() != State::Running { pcm.start().unwrap() };
-    // Wait for the stream to finish playback.
-    pcm.drain().unwrap();
+    let mut player = Player::new(Box::new(MidiPlayer::with_synth(file, synth, output)));
+    player.play()
 }
@@ -0,0 +1,32 @@
+use std::env;
+use std::process;
+
+use obs::config::EngineConfig;
+use obs::io::midi_writer::MidiWriter;
+use obs::io::score_reader::Score;
+use obs::{Error, Result};
+
+const USAGE: &str = "usage: score2midi <out.mid> <score.txt>...";
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("score2midi: {err}");
+        process::exit(1);
+    }
+}
+
+/// writes the scores, one per channel, to a Type 1 midi file
+fn run() -> Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    // an option where the output belongs would otherwise be written over as a file
+    let (out, paths) = match args.split_first() {
+        Some((out, paths)) if !paths.is_empty() && !args.iter().any(|arg| arg.starts_with("--")) => (out, paths),
+        _ => return Err(Error::Usage(USAGE.to_string())),
+    };
+
+    let mut score = Score::open(paths, &EngineConfig::default())?;
+    let writer = MidiWriter::from_score(&mut score);
+    writer.save(out)?;
+    println!("{out}: {} notes", writer.len());
+    Ok(())
+}
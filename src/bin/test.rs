@@ -1,69 +1,40 @@
-use alsa::Direction;
-use alsa::pcm::PCM;
-use midly::stream::Buffer;
 use obs::synth::drum_machine::{DrumMachine, DrumVoice};
-use obs::io::player::{Player, PlayerKind};
 use std::fs::File;
 use std::path::Path;
-use std::io::{BufReader, BufWriter, Write};
-use wav;
 
-use obs::synth;
-use obs::synth::{drum_machine};
-use obs::{utils, io::midi_reader};
+use obs::config::EngineConfig;
 use obs::io::wav_reader;
-use obs::SAMPLE_RATE;
-use obs::AMPLITUDE_MAX;
-
-fn main() {
-    let pcm = PCM::new("default", Direction::Playback, false).unwrap();
-
-    utils::set_pcm_params(&pcm);
-
-    let io = pcm.io_u8().unwrap();
-
-    let mut synth: synth::Synth = Default::default();
-
-    let file1 = File::open("musicas/badapple_nomico_lead.txt").unwrap();
-    let file2 = File::open("musicas/badapple_nomico_bass.txt").unwrap();
-    let file3 = File::open("musicas/badapple_nomico8.txt").unwrap();
-    let file4 = File::open("musicas/badapple_nomico7.txt").unwrap();
-
-    let mut readers = [BufReader::new(file1), BufReader::new(file2), BufReader::new(file3), BufReader::new(file4)];
-
-    // synth.play_files(&mut readers, &io);
-
-    // midi_reader::play();
-    // let mut reader = midi_reader::MidiReader::new(include_bytes!("../../musicas/ice_cap.mid"));
-    // reader.list_tracks();
-
-    let buffer = wav_reader::get_sample(File::open(Path::new("samples/snare")).unwrap());
-    // let mut drums = DrumVoice::new(138, 180, 0.45);
-    let mut player = Player::new(PlayerKind::KeyboardPlayer);
+use obs::io::audio_out::AudioOut;
+use obs::io::player::{Player, ScorePlayer};
+use obs::io::score_reader::Score;
+use obs::io::wav_writer::WavSink;
+
+fn main() -> obs::Result<()> {
+    let config = EngineConfig::default();
+
+    // one score file per channel
+    let score = Score::open(&[
+        "musicas/badapple_nomico_lead.txt",
+        "musicas/badapple_nomico_bass.txt",
+        "musicas/badapple_nomico8.txt",
+        "musicas/badapple_nomico7.txt",
+    ], &config)?;
+    let sink = WavSink::new("score.wav", &config)?.with_listening_copy("score_44k.wav", 44_100);
+    let output = AudioOut::with_sink(Box::new(sink), &config);
+    let mut player = Player::new(Box::new(ScorePlayer::with_output(score, output)));
+    player.play()?;
+
+    let buffer = wav_reader::get_sample(File::open(Path::new("samples/snare"))?, &config)?;
+    // let mut drums = DrumVoice::new(138, 180, 0.45, &config);
     let mut drums = DrumMachine::new();
-    drums.load_voice(DrumVoice::new(buffer));
+    let snare = drums.load_voice(DrumVoice::new(buffer));
+    drums.hit(snare);
 
     // write to wav
-    // let header = wav::Header::new(1, 1, SAMPLE_RATE, 8);
-    // let mut writer = BufWriter::new(File::create("output.wav").expect("Failed to create WAV file"));
-    // let mut buffer = vec![];
-    // for _ in 0..SAMPLE_RATE * 2 {
-    //     if drums.get_sample() {
-    //         buffer.push(AMPLITUDE)
-    //     } else {
-    //         buffer.push(0)
-    //     }
-    // }
-    // let track = wav::BitDepth::Eight(buffer);
-    // wav::write(header, &track, &mut writer).unwrap();
-
-    // play
-    // loop {
-    //     player.audio_out(drums.get_sample())
-    // }
-    
-    // In case the buffer was larger than 2 seconds, start the stream manually.
-    // if pcm.state() != State::Running { pcm.start().unwrap() };
-    // Wait for the stream to finish playback.
-    pcm.drain().unwrap();
+    let sink = WavSink::new("output.wav", &config)?.with_listening_copy("output_44k.wav", 44_100);
+    let mut output = AudioOut::with_sink(Box::new(sink), &config);
+    for _ in 0..config.sample_rate * 2 {
+        output.audio_out(if drums.get_sample() { config.amplitude_max } else { 0 })?;
+    }
+    output.drain()
 }
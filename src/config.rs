@@ -0,0 +1,79 @@
+use crate::{Error, Result};
+use crate::{AMPLITUDE_MAX, AMPLITUDE_MIN, BUFFER_SIZE, CHANNELS_MAX, DUTY_MAX, SAMPLE_RATE, VOICES_MAX};
+
+/// engine parameters shared by `Synth`, its channels and voices, the samplers and `AudioOut`
+///
+/// defaults to the crate level constants
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EngineConfig {
+    pub sample_rate: u32,       // samples per second
+    pub buffer_size: usize,     // samples per block handed to the output
+    pub voices_max: usize,      // voices per channel
+    pub channels_max: usize,    // channels per synth
+    pub amplitude_max: u8,      // value of a high sample
+    pub duty_max: u32,          // resolution of integer duty cycles
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            sample_rate: SAMPLE_RATE,
+            buffer_size: BUFFER_SIZE,
+            voices_max: VOICES_MAX,
+            channels_max: CHANNELS_MAX,
+            amplitude_max: AMPLITUDE_MAX,
+            duty_max: DUTY_MAX,
+        }
+    }
+}
+
+impl EngineConfig {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// same config running at `sample_rate`
+    pub fn with_sample_rate(mut self, sample_rate: u32) -> Self {
+        self.sample_rate = sample_rate;
+        self
+    }
+
+    /// same config writing blocks of `buffer_size` samples
+    pub fn with_buffer_size(mut self, buffer_size: usize) -> Self {
+        self.buffer_size = buffer_size;
+        self
+    }
+
+    /// checks that every parameter is usable
+    pub fn validate(&self) -> Result<()> {
+        if self.sample_rate == 0 {
+            return Err(Error::Config("sample rate must be positive".to_string()));
+        }
+        if self.buffer_size == 0 {
+            return Err(Error::Config("buffer size must be positive".to_string()));
+        }
+        if self.voices_max == 0 {
+            return Err(Error::Config("a channel needs at least one voice".to_string()));
+        }
+        if self.channels_max == 0 {
+            return Err(Error::Config("a synth needs at least one channel".to_string()));
+        }
+        if self.amplitude_max == AMPLITUDE_MIN {
+            return Err(Error::Config(format!("maximum amplitude must differ from {AMPLITUDE_MIN}")));
+        }
+        if self.duty_max == 0 {
+            return Err(Error::Config("duty resolution must be positive".to_string()));
+        }
+        Ok(())
+    }
+
+    /// same config adjusted to the rate and buffer size an output device actually accepted
+    ///
+    /// blocks are shrunk to fit the device's buffer
+    pub fn fit_device(&self, sample_rate: u32, buffer_size: usize) -> Self {
+        let mut config = self.with_sample_rate(sample_rate);
+        config.buffer_size = self.buffer_size.min(buffer_size.max(1));
+        config
+    }
+}
@@ -0,0 +1,74 @@
+use std::fmt;
+
+/// everything that can go wrong while loading files or talking to the sound card
+#[derive(Debug)]
+pub enum Error {
+    /// the ALSA device or sequencer could not be opened or configured
+    Alsa(alsa::Error),
+    /// a file could not be read or written
+    Io(std::io::Error),
+    /// a midi file is malformed
+    MidiParse(midly::Error),
+    /// a recording holds something a midi file can't
+    MidiWrite(String),
+    /// a wav file uses a format that can't be turned into 1 bit samples
+    WavFormat(String),
+    /// a text score is malformed
+    ScoreParse(String),
+    /// a Scala scale or keyboard mapping is malformed
+    ScalaParse(String),
+    /// an engine parameter is unusable
+    Config(String),
+    /// the audio thread stopped without finishing what it was rendering
+    Render(String),
+    /// a program was given arguments it doesn't understand, holding its usage
+    Usage(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Alsa(err) => write!(f, "audio device error: {err}"),
+            Error::Io(err) => write!(f, "io error: {err}"),
+            Error::MidiParse(err) => write!(f, "invalid midi file: {err}"),
+            Error::MidiWrite(message) => write!(f, "can't write midi file: {message}"),
+            Error::WavFormat(message) => write!(f, "unsupported wav file: {message}"),
+            Error::ScoreParse(message) => write!(f, "invalid score: {message}"),
+            Error::ScalaParse(message) => write!(f, "invalid scala file: {message}"),
+            Error::Config(message) => write!(f, "invalid engine config: {message}"),
+            Error::Render(message) => write!(f, "rendering failed: {message}"),
+            Error::Usage(usage) => write!(f, "{usage}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Alsa(err) => Some(err),
+            Error::Io(err) => Some(err),
+            Error::MidiParse(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<alsa::Error> for Error {
+    fn from(err: alsa::Error) -> Self {
+        Error::Alsa(err)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<midly::Error> for Error {
+    fn from(err: midly::Error) -> Self {
+        Error::MidiParse(err)
+    }
+}
@@ -1,4 +1,14 @@
 pub mod midi_reader;
+pub mod midi_writer;
+pub mod midi_input;
+pub mod tempo_map;
+pub mod midi_stats;
 pub mod player;
 pub mod wav_reader;
-pub mod audio_out;
\ No newline at end of file
+pub mod wav_writer;
+pub mod audio_out;
+pub mod scala_reader;
+pub mod score_reader;
+pub mod score_writer;
+pub mod ring_buffer;
+pub mod audio_thread;
@@ -1,44 +1,201 @@
+use std::io::ErrorKind;
+
 use alsa::Direction;
-use alsa::pcm::{Access, Format, HwParams, IO, PCM};
+use alsa::pcm::{Access, Format, HwParams, PCM};
 use alsa::ValueOr;
-use crate::{AMPLITUDE_MAX, AMPLITUDE_MIN, BUFFER_SIZE, SAMPLE_RATE};
+use crate::config::EngineConfig;
+use crate::synth::Synth;
+use crate::Result;
 
-pub fn set_pcm_params(pcm: &alsa::PCM) {
-    let hwp = HwParams::any(&pcm).unwrap();
-    hwp.set_channels(1).unwrap();
-    hwp.set_rate(SAMPLE_RATE, ValueOr::Nearest).unwrap();
-    hwp.set_format(Format::U8).unwrap();
-    hwp.set_access(Access::RWInterleaved).unwrap();
-    pcm.hw_params(&hwp).unwrap();
+/// hardware parameters a PCM device settled on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PcmParams {
+    pub rate: u32,              // samples per second
+    pub period_size: usize,     // samples per period
+    pub buffer_size: usize,     // samples in the device's ring buffer
 }
 
-pub struct AudioOut {
+/// configures `pcm` for unsigned 8 bit mono playback at `config`'s sample rate, or
+/// the nearest rate the device supports
+///
+/// returns the parameters the device actually accepted
+pub fn set_pcm_params(pcm: &alsa::PCM, config: &EngineConfig) -> Result<PcmParams> {
+    let hwp = HwParams::any(pcm)?;
+    hwp.set_channels(1)?;
+    hwp.set_rate(config.sample_rate, ValueOr::Nearest)?;
+    hwp.set_format(Format::U8)?;
+    hwp.set_access(Access::RWInterleaved)?;
+    pcm.hw_params(&hwp)?;
+
+    let hwp = pcm.hw_params_current()?;
+    Ok(PcmParams {
+        rate: hwp.get_rate()?,
+        period_size: hwp.get_period_size()? as usize,
+        buffer_size: hwp.get_buffer_size()? as usize,
+    })
+}
+
+/// destination for the u8 sample stream produced by `Synth`
+pub trait OutputSink: Send {
+    /// consumes a block of samples at the engine's `sample_rate`
+    fn write(&mut self, buffer: &[u8]) -> Result<()>;
+
+    /// blocks until every sample written so far has been output
+    fn drain(&mut self) -> Result<()>;
+
+    /// hardware parameters, for sinks backed by a sound card
+    fn params(&self) -> Option<PcmParams> {
+        None
+    }
+
+    /// times the sink ran out of samples, for sinks that play in real time
+    fn underruns(&self) -> u64 {
+        0
+    }
+}
+
+/// plays samples on the ALSA "default" PCM device
+pub struct AlsaSink {
     pcm: PCM,
-    buffer: Vec<u8>
+    params: PcmParams,
+    underruns: u64,
+}
+
+impl AlsaSink {
+
+    /// opens the device, failing if it can't play unsigned 8 bit mono
+    pub fn new(config: &EngineConfig) -> Result<Self> {
+        let pcm = PCM::new("default", Direction::Playback, false)?;
+        let params = set_pcm_params(&pcm, config)?;
+        Ok(Self {
+            pcm,
+            params,
+            underruns: 0,
+        })
+    }
+}
+
+impl OutputSink for AlsaSink {
+    /// writes the whole block, restarting the device if it ran out of samples
+    fn write(&mut self, buffer: &[u8]) -> Result<()> {
+        let io = self.pcm.io_u8()?;
+        let mut written = 0;
+        while written < buffer.len() {
+            match io.writei(&buffer[written..]) {
+                Ok(n) => written += n,
+                // the device ran dry, it has to be prepared again before it plays
+                Err(err) if std::io::Error::from_raw_os_error(err.errno()).kind() == ErrorKind::BrokenPipe => {
+                    self.underruns += 1;
+                    self.pcm.prepare()?;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(())
+    }
+
+    fn drain(&mut self) -> Result<()> {
+        self.pcm.drain()?;
+        Ok(())
+    }
+
+    fn params(&self) -> Option<PcmParams> {
+        Some(self.params)
+    }
+
+    fn underruns(&self) -> u64 {
+        self.underruns
+    }
+}
+
+/// buffers samples and hands them to an `OutputSink` in blocks of `buffer_size`
+pub struct AudioOut {
+    sink: Box<dyn OutputSink>,
+    buffer: Vec<u8>,
+    config: EngineConfig,
 }
 
 impl AudioOut {
 
-    pub fn new() -> Self {
-        let pcm = PCM::new("default", Direction::Playback, false).unwrap();
-        set_pcm_params(&pcm);
-        let buffer = vec![];
+    /// opens the ALSA "default" PCM device
+    ///
+    /// the device may settle on a different rate than requested, so the synth
+    /// feeding it should be built from `config()` rather than `config`
+    pub fn new(config: &EngineConfig) -> Result<Self> {
+        let sink = AlsaSink::new(config)?;
+        let params = sink.params;
+        let config = config.fit_device(params.rate, params.buffer_size);
+        Ok(Self::with_sink(Box::new(sink), &config))
+    }
+
+    /// sends samples to `sink` instead of a sound card
+    pub fn with_sink(sink: Box<dyn OutputSink>, config: &EngineConfig) -> Self {
         Self {
-            pcm,
-            buffer,
+            sink,
+            buffer: Vec::with_capacity(config.buffer_size),
+            config: *config,
         }
     }
 
-    pub fn audio_out(&mut self, sample: u8) {
+    /// engine config matching what the output actually plays
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    /// samples per second the output plays at
+    pub fn sample_rate(&self) -> u32 {
+        self.config.sample_rate
+    }
+
+    /// hardware parameters the sound card settled on, `None` for file sinks
+    pub fn params(&self) -> Option<PcmParams> {
+        self.sink.params()
+    }
+
+    /// times the sound card ran out of samples and had to be restarted
+    pub fn underruns(&self) -> u64 {
+        self.sink.underruns()
+    }
+
+    /// buffers one sample, handing the buffer to the sink once full
+    pub fn audio_out(&mut self, sample: u8) -> Result<()> {
         self.buffer.push(sample);
-        if self.buffer.len() >= BUFFER_SIZE {
-            let io = self.pcm.io_u8().unwrap();
-            io.writei(&self.buffer).unwrap();
+        if self.buffer.len() >= self.config.buffer_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// hands a whole block to the sink, after any buffered samples
+    pub fn write(&mut self, block: &[u8]) -> Result<()> {
+        self.flush()?;
+        self.sink.write(block)
+    }
+
+    /// pulls `n_samples` samples from `synth` into the sink, one block at a time
+    pub fn render(&mut self, synth: &mut Synth, n_samples: usize) -> Result<()> {
+        let mut block = Vec::with_capacity(self.config.buffer_size);
+        for _ in 0..n_samples {
+            block.push(synth.get_sample());
+            if block.len() == self.config.buffer_size {
+                self.write(&block)?;
+                block.clear();
+            }
+        }
+        self.write(&block)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if !self.buffer.is_empty() {
+            self.sink.write(&self.buffer)?;
             self.buffer.clear();
         }
+        Ok(())
     }
 
-    pub fn drain(&mut self) {
-        self.pcm.drain().unwrap();
+    /// flushes any partially filled buffer and drains the sink
+    pub fn drain(&mut self) -> Result<()> {
+        self.flush()?;
+        self.sink.drain()
     }
-}
\ No newline at end of file
+}
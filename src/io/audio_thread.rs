@@ -0,0 +1,183 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::io::audio_out::AudioOut;
+use crate::io::player::PlayerEvent;
+use crate::io::ring_buffer::{ring_buffer, Consumer, Producer};
+use crate::synth::Synth;
+use crate::{Error, Result};
+
+/// number of events that can wait for the audio thread
+const EVENT_QUEUE_SIZE: usize = 4096;
+
+/// a `PlayerEvent` due once `sample` samples have been rendered
+#[derive(Clone, Debug)]
+pub struct TimedEvent {
+    pub sample: u64,
+    pub event: PlayerEvent,
+}
+
+/// state the audio thread shares with the thread controlling it
+struct Clock {
+    rendered: AtomicU64,    // samples rendered so far
+    horizon: AtomicU64,     // samples the audio thread may render before waiting for more events
+    underruns: AtomicU64,   // times the sound card ran out of samples
+    running: AtomicBool,
+}
+
+/// owns a `Synth` and an `AudioOut` on a dedicated thread, applying the events
+/// queued to it at the sample they are stamped with
+///
+/// the queue is a lock free ring buffer, so feeding it never blocks the audio
+/// thread. a free running thread plays continuously, for live input, while a
+/// sequenced one only renders up to the horizon its producer has `advance`d to,
+/// so it never gets ahead of events that are yet to be queued
+pub struct AudioThread {
+    events: Producer<TimedEvent>,
+    clock: Arc<Clock>,
+    sample_rate: u32,
+    handle: Option<JoinHandle<Result<()>>>,
+}
+
+impl AudioThread {
+
+    /// plays continuously, applying events as soon as they arrive
+    pub fn spawn(synth: Synth, output: AudioOut) -> Self {
+        Self::start(synth, output, u64::MAX)
+    }
+
+    /// only plays up to the horizon set with `advance`
+    pub fn spawn_sequenced(synth: Synth, output: AudioOut) -> Self {
+        Self::start(synth, output, 0)
+    }
+
+    fn start(synth: Synth, output: AudioOut, horizon: u64) -> Self {
+        let (events, queue) = ring_buffer(EVENT_QUEUE_SIZE);
+        let clock = Arc::new(Clock {
+            rendered: AtomicU64::new(0),
+            horizon: AtomicU64::new(horizon),
+            underruns: AtomicU64::new(0),
+            running: AtomicBool::new(true),
+        });
+        let sample_rate = output.sample_rate();
+        let thread_clock = clock.clone();
+        let handle = thread::spawn(move || Self::run(synth, output, queue, &thread_clock));
+        Self {
+            events,
+            clock,
+            sample_rate,
+            handle: Some(handle),
+        }
+    }
+
+    /// the audio thread's loop, rendering one block at a time
+    fn run(mut synth: Synth, mut output: AudioOut, mut queue: Consumer<TimedEvent>, clock: &Clock) -> Result<()> {
+        let mut block = vec![0; output.config().buffer_size];
+        let mut sample = 0;
+
+        while clock.running.load(Ordering::Acquire) {
+            let horizon = clock.horizon.load(Ordering::Acquire);
+            let n_samples = horizon.saturating_sub(sample).min(block.len() as u64) as usize;
+            if n_samples == 0 {
+                // events due on the sample waited at are applied already, freeing their
+                // room in the queue for a producer that filled it with events due together
+                Self::apply_due(&mut synth, &mut queue, sample);
+                thread::sleep(Duration::from_micros(100));
+                continue;
+            }
+
+            for out in block[..n_samples].iter_mut() {
+                Self::apply_due(&mut synth, &mut queue, sample);
+                *out = synth.get_sample();
+                sample += 1;
+            }
+
+            output.write(&block[..n_samples])?;
+            clock.rendered.store(sample, Ordering::Release);
+            clock.underruns.store(output.underruns(), Ordering::Relaxed);
+        }
+
+        output.drain()
+    }
+
+    /// applies every queued event due by sample `sample`
+    fn apply_due(synth: &mut Synth, queue: &mut Consumer<TimedEvent>, sample: u64) {
+        while let Some(timed) = queue.pop_if(|timed| timed.sample <= sample) {
+            timed.event.apply(synth);
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// samples rendered so far
+    pub fn now(&self) -> u64 {
+        self.clock.rendered.load(Ordering::Acquire)
+    }
+
+    /// times the sound card ran out of samples and had to be restarted
+    pub fn underruns(&self) -> u64 {
+        self.clock.underruns.load(Ordering::Relaxed)
+    }
+
+    /// whether the thread is still rendering, which it stops doing when stopped or
+    /// when writing to the output fails
+    pub fn is_running(&self) -> bool {
+        self.handle.as_ref().is_some_and(|handle| !handle.is_finished())
+    }
+
+    /// queues `event` to be applied at sample `sample`, handing it back if the queue is
+    /// full or the thread is no longer running
+    pub fn schedule(&mut self, sample: u64, event: PlayerEvent) -> std::result::Result<(), PlayerEvent> {
+        if !self.is_running() {
+            return Err(event);
+        }
+        self.events.push(TimedEvent { sample, event }).map_err(|timed| timed.event)
+    }
+
+    /// queues `event` to be applied right away, waiting for room in the queue if needed
+    pub fn send(&mut self, event: PlayerEvent) {
+        let mut event = event;
+        while let Err(full) = self.schedule(self.now(), event) {
+            if !self.is_running() {
+                return;
+            }
+            event = full;
+            thread::sleep(Duration::from_micros(100));
+        }
+    }
+
+    /// lets a sequenced thread render up to sample `horizon`
+    ///
+    /// every event due before `horizon` must have been scheduled already
+    pub fn advance(&self, horizon: u64) {
+        self.clock.horizon.fetch_max(horizon, Ordering::Release);
+    }
+
+    /// stops rendering and waits for the thread to finish, `None` if it already was
+    fn halt(&mut self) -> Option<thread::Result<Result<()>>> {
+        self.clock.running.store(false, Ordering::Release);
+        self.handle.take().map(|handle| handle.join())
+    }
+
+    /// stops rendering, drains the output and waits for the thread to finish, returning
+    /// why it stopped early if it did
+    pub fn stop(&mut self) -> Result<()> {
+        match self.halt() {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => Err(Error::Render("the audio thread panicked".to_string())),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for AudioThread {
+    /// stops the thread, ignoring how it ended, as panicking while already
+    /// unwinding would abort
+    fn drop(&mut self) {
+        let _ = self.halt();
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::VecDeque;
+use std::ffi::CString;
+use std::sync::{Arc, Mutex};
+
+use alsa::seq::{Addr, EvCtrl, EvNote, EventType, PortCap, PortSubscribe, PortType, Seq};
+use alsa::Direction;
+
+use crate::io::midi_reader::{MidiEvent, MidiEventKind};
+use crate::Result;
+
+/// name the sequencer client and its port show up with
+pub const SEQ_CLIENT_NAME: &str = "obs";
+pub const SEQ_PORT_NAME: &str = "input";
+
+/// source of midi events played live
+pub trait MidiInput: Send {
+    /// next event received, or `None` if none is waiting, without blocking
+    fn poll(&mut self) -> Result<Option<MidiEvent>>;
+
+    /// whether no more events will ever come
+    fn is_closed(&self) -> bool {
+        false
+    }
+}
+
+/// midi input port of an ALSA sequencer client, for hardware controllers and
+/// other programs to connect to
+pub struct SeqInput {
+    seq: Seq,
+    port: i32,
+}
+
+impl SeqInput {
+
+    /// creates the `obs` sequencer client with a writable `input` port
+    pub fn new() -> Result<Self> {
+        let seq = Seq::open(None, Some(Direction::Capture), true)?;
+        let client_name = CString::new(SEQ_CLIENT_NAME).unwrap();
+        seq.set_client_name(&client_name)?;
+
+        let port_name = CString::new(SEQ_PORT_NAME).unwrap();
+        let port = seq.create_simple_port(
+            &port_name,
+            PortCap::WRITE | PortCap::SUBS_WRITE,
+            PortType::MIDI_GENERIC | PortType::APPLICATION,
+        )?;
+        Ok(Self { seq, port })
+    }
+
+    /// address other clients send to, as `client:port`
+    pub fn addr(&self) -> Result<Addr> {
+        Ok(Addr { client: self.seq.client_id()?, port: self.port })
+    }
+
+    /// receives whatever the sequencer port at `sender` plays, like `aconnect sender obs`
+    pub fn connect(&self, sender: Addr) -> Result<()> {
+        let subscription = PortSubscribe::empty()?;
+        subscription.set_sender(sender);
+        subscription.set_dest(self.addr()?);
+        self.seq.subscribe_port(&subscription)?;
+        Ok(())
+    }
+
+    /// converts a sequencer event, `None` for the ones that aren't channel messages
+    fn seq2event(event: &alsa::seq::Event) -> Option<MidiEvent> {
+        let note = || event.get_data::<EvNote>();
+        let ctrl = || event.get_data::<EvCtrl>();
+        let (kind, channel) = match event.get_type() {
+            // by convention, a NoteOn message with 0 velocity should be treated as a NoteOff
+            EventType::Noteon => note().map(|ev| match ev.velocity {
+                0 => (MidiEventKind::NoteOff { key: ev.note, velocity: 0 }, ev.channel),
+                velocity => (MidiEventKind::NoteOn { key: ev.note, velocity }, ev.channel),
+            })?,
+            EventType::Noteoff => {
+                note().map(|ev| (MidiEventKind::NoteOff { key: ev.note, velocity: ev.velocity }, ev.channel))?
+            }
+            EventType::Keypress => {
+                note().map(|ev| (MidiEventKind::PolyAftertouch { key: ev.note, pressure: ev.velocity }, ev.channel))?
+            }
+            EventType::Controller => ctrl().map(|ev| {
+                let kind = MidiEventKind::ControlChange { controller: ev.param as u8, value: ev.value as u8 };
+                (kind, ev.channel)
+            })?,
+            EventType::Pgmchange => ctrl().map(|ev| (MidiEventKind::ProgramChange(ev.value as u8), ev.channel))?,
+            EventType::Chanpress => ctrl().map(|ev| (MidiEventKind::ChannelAftertouch(ev.value as u8), ev.channel))?,
+            EventType::Pitchbend => {
+                ctrl().map(|ev| (MidiEventKind::PitchBend(ev.value.clamp(-8192, 8191) as i16), ev.channel))?
+            }
+            _ => return None,
+        };
+        Some(MidiEvent::new(kind, channel))
+    }
+}
+
+impl MidiInput for SeqInput {
+    fn poll(&mut self) -> Result<Option<MidiEvent>> {
+        let mut input = self.seq.input();
+        // skips events that don't translate to channel messages, such as port subscriptions
+        while input.event_input_pending(true)? > 0 {
+            let event = input.event_input()?;
+            if let Some(event) = Self::seq2event(&event) {
+                return Ok(Some(event));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// queue of events fed from code, for when there is no sequencer to read from
+///
+/// clones share the same queue, so one can be fed while a player reads another
+#[derive(Clone, Debug, Default)]
+pub struct MemoryInput {
+    queue: Arc<Mutex<(VecDeque<MidiEvent>, bool)>>,    // events waiting and whether the input is closed
+}
+
+impl MemoryInput {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// input already holding `events`
+    pub fn from_events(events: impl IntoIterator<Item = MidiEvent>) -> Self {
+        let input = Self::new();
+        for event in events {
+            input.push(event);
+        }
+        input
+    }
+
+    /// queues `event` to be received
+    pub fn push(&self, event: MidiEvent) {
+        self.queue.lock().unwrap().0.push_back(event);
+    }
+
+    /// marks the input as closed once the queued events have been received
+    pub fn close(&self) {
+        self.queue.lock().unwrap().1 = true;
+    }
+}
+
+impl MidiInput for MemoryInput {
+    fn poll(&mut self) -> Result<Option<MidiEvent>> {
+        Ok(self.queue.lock().unwrap().0.pop_front())
+    }
+
+    fn is_closed(&self) -> bool {
+        let queue = self.queue.lock().unwrap();
+        queue.1 && queue.0.is_empty()
+    }
+}
@@ -1,54 +1,141 @@
 use midly::{Smf};
-use num_traits::pow;
 
-use crate::synth::channel::Channel;
+use crate::io::midi_stats::MidiStats;
+use crate::io::tempo_map::{MidiTiming, TempoMap};
+use crate::Result;
 
-#[derive(Clone, Copy, Debug)]
-enum MidiEventKind {
-    NoteOff,
-    NoteOn,
-    MetaSetTempo(u32),
+/// decodes midi text, which has no set encoding
+fn text(data: &[u8]) -> String {
+    String::from_utf8_lossy(data).into_owned()
+}
+
+/// what a midi event does, channel events first, then system exclusive and meta events
+#[derive(Clone, Debug, PartialEq)]
+pub enum MidiEventKind {
+    NoteOff { key: u8, velocity: u8 },
+    NoteOn { key: u8, velocity: u8 },
+    PolyAftertouch { key: u8, pressure: u8 },
+    ControlChange { controller: u8, value: u8 },
+    ProgramChange(u8),
+    ChannelAftertouch(u8),
+    PitchBend(i16),                     // -8192 to 8191, 0 being no bend
+    SysEx(Vec<u8>),
+    MetaSetTempo(u32),                  // microseconds per beat
+    MetaTimeSignature {
+        numerator: u8,
+        denominator: u8,                // the actual note value, e.g. 8 for 6/8
+        clocks_per_click: u8,           // midi clocks per metronome click
+        notes_per_quarter: u8,          // 32nd notes per quarter note
+    },
+    MetaKeySignature { sharps: i8, minor: bool },   // flats are negative sharps
+    MetaText(String),
+    MetaTrackName(String),
+    MetaInstrumentName(String),
+    MetaLyric(String),
+    MetaMarker(String),
+    MetaEndOfTrack,
     Other,
 }
 
-#[derive(Clone, Copy)]
+/// an event of a midi track along with when it happens
+#[derive(Clone, Debug, PartialEq)]
 pub struct MidiEvent {
     kind: MidiEventKind,
-    key: u8,
-    velocity: u8,
-    delta_tick: u32,
-    channel: u8,
+    channel: u8,        // 0 for events that are not channel events
+    delta_tick: u32,    // ticks since the previous event of the track
+    tick: u64,          // absolute tick
+    us: u64,            // absolute time in microseconds
 }
 
 impl MidiEvent {
-    pub fn kind(self) -> MidiEventKind {
-        self.kind
+
+    /// event of midi channel `channel` outside of any track, as received live
+    pub fn new(kind: MidiEventKind, channel: u8) -> Self {
+        Self {
+            kind,
+            channel: channel & 0x0F,
+            delta_tick: 0,
+            tick: 0,
+            us: 0,
+        }
     }
 
-    pub fn key(self) -> u8 {
-        self.key
+    pub fn kind(&self) -> &MidiEventKind {
+        &self.kind
     }
 
-    pub fn velocity(self) -> u8 {
-        self.velocity
+    /// key of note and aftertouch events, 0 for anything else
+    pub fn key(&self) -> u8 {
+        match self.kind {
+            MidiEventKind::NoteOff { key, .. }
+            | MidiEventKind::NoteOn { key, .. }
+            | MidiEventKind::PolyAftertouch { key, .. } => key,
+            _ => 0,
+        }
     }
 
-    pub fn delta_tick(self) -> u32 {
+    /// velocity of note events, 0 for anything else
+    pub fn velocity(&self) -> u8 {
+        match self.kind {
+            MidiEventKind::NoteOff { velocity, .. } | MidiEventKind::NoteOn { velocity, .. } => velocity,
+            _ => 0,
+        }
+    }
+
+    pub fn delta_tick(&self) -> u32 {
         self.delta_tick
     }
 
-    pub fn channel(self) -> u8 {
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    pub fn us(&self) -> u64 {
+        self.us
+    }
+
+    pub fn channel(&self) -> u8 {
         self.channel
     }
 
-    
+    /// whether the event belongs to a midi channel
+    pub fn is_channel_event(&self) -> bool {
+        matches!(
+            self.kind,
+            MidiEventKind::NoteOff { .. }
+                | MidiEventKind::NoteOn { .. }
+                | MidiEventKind::PolyAftertouch { .. }
+                | MidiEventKind::ControlChange { .. }
+                | MidiEventKind::ProgramChange(_)
+                | MidiEventKind::ChannelAftertouch(_)
+                | MidiEventKind::PitchBend(_)
+        )
+    }
 }
 
+/// a note of a track, from its NoteOn to the matching NoteOff
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct MidiNote {
-    key: u8,
-    velocity: u8,
-    start_time: u8,
-    duration: u8,
+    pub channel: u8,
+    pub key: u8,
+    pub velocity: u8,
+    pub start_tick: u64,        // absolute tick of the NoteOn
+    pub start_us: u64,          // absolute time of the NoteOn in microseconds
+    pub duration_ticks: u64,
+    pub duration_us: u64,
+}
+
+impl MidiNote {
+
+    /// absolute tick of the NoteOff
+    pub fn end_tick(&self) -> u64 {
+        self.start_tick + self.duration_ticks
+    }
+
+    /// absolute time of the NoteOff in microseconds
+    pub fn end_us(&self) -> u64 {
+        self.start_us + self.duration_us
+    }
 }
 
 pub struct MidiTrack {
@@ -63,155 +150,217 @@ impl MidiTrack {
     pub fn get_event(&self, index: usize) -> &MidiEvent {
         &self.events[index]
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn instrument(&self) -> &str {
+        &self.instrument
+    }
+
+    pub fn events(&self) -> &[MidiEvent] {
+        &self.events
+    }
+
+    /// notes of the track in order of their start
+    pub fn notes(&self) -> &[MidiNote] {
+        &self.notes
+    }
+
+    /// absolute tick of the last event
+    pub fn end_tick(&self) -> u64 {
+        self.events.last().map_or(0, |event| event.tick)
+    }
+
+    /// absolute time of the last event in microseconds
+    pub fn end_us(&self) -> u64 {
+        self.events.last().map_or(0, |event| event.us)
+    }
+
+    /// pairs every NoteOn with the NoteOff of the same channel and key that follows it,
+    /// the earliest NoteOn being paired first
+    ///
+    /// notes left sounding stop with the last event of the track
+    fn pair_notes(&mut self) {
+        let mut notes = vec![];
+        let mut held: Vec<usize> = vec![];     // indices in `notes` of the notes still sounding, oldest first
+
+        let close = |note: &mut MidiNote, tick: u64, us: u64| {
+            note.duration_ticks = tick - note.start_tick;
+            note.duration_us = us - note.start_us;
+        };
+
+        for event in self.events.iter() {
+            match event.kind {
+                MidiEventKind::NoteOn { key, velocity } => {
+                    held.push(notes.len());
+                    notes.push(MidiNote {
+                        channel: event.channel,
+                        key,
+                        velocity,
+                        start_tick: event.tick,
+                        start_us: event.us,
+                        duration_ticks: 0,
+                        duration_us: 0,
+                    });
+                }
+                MidiEventKind::NoteOff { key, .. } => {
+                    let sounding = held.iter()
+                        .position(|&i| notes[i].channel == event.channel && notes[i].key == key);
+                    if let Some(j) = sounding {
+                        let i = held.remove(j);
+                        close(&mut notes[i], event.tick, event.us);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let (tick, us) = (self.end_tick(), self.end_us());
+        for i in held {
+            close(&mut notes[i], tick, us);
+        }
+        self.notes = notes;
+    }
 }
 
 pub struct MidiFile {
     tracks: Vec<MidiTrack>,
-    tempo: u32,
-    ticks_per_beat: u16,
+    tempo_map: TempoMap,
 }
 
 impl MidiFile {
-    pub fn new(f: &[u8]) -> Self {
-        let smf = Smf::parse(f).unwrap();
-        let tempo = 500_000;        // default midi tempo
-        let tracks = Self::parse_tracks(&smf);
-        let ticks_per_beat = match smf.header.timing {
-            midly::Timing::Metrical(value) => value.as_int(),
-            midly::Timing::Timecode(_, _) => {
-                // Handle Timecode variant if needed
-                // For now, just use a default value
-                0
-            }
+    /// parses a standard midi file
+    pub fn new(f: &[u8]) -> Result<Self> {
+        let smf = Smf::parse(f)?;
+        let mut file = Self {
+            tracks: Self::parse_tracks(&smf),
+            tempo_map: TempoMap::new(smf.header.timing.into()),
         };
-        Self {
-            tracks,
-            tempo,
-            ticks_per_beat,
+        file.build_tempo_map();
+        for track in file.tracks.iter_mut() {
+            track.pair_notes();
         }
+        Ok(file)
     }
 
-    pub fn get_next_event(&mut self, track_n: usize) -> MidiEvent {
+    /// returns the next event of track `track_n`, or `None` if the track is exhausted
+    pub fn get_next_event(&mut self, track_n: usize) -> Option<MidiEvent> {
         let track = &mut self.tracks[track_n];
-        let cursor = &mut track.cursor;
-        let event = track.events[*cursor];
-        *cursor += 1;
-        event
+        let event = track.events.get(track.cursor)?.clone();
+        track.cursor += 1;
+        Some(event)
+    }
+
+    /// returns the next event across all tracks in absolute tick order, along with
+    /// its absolute time in microseconds
+    ///
+    /// returns `None` once every track is exhausted
+    pub fn next_event(&mut self) -> Option<(u64, MidiEvent)> {
+        self.next_track_event().map(|(us, _, event)| (us, event))
+    }
+
+    /// same as `next_event`, along with the index of the track the event is in
+    pub fn next_track_event(&mut self) -> Option<(u64, usize, MidiEvent)> {
+        let (track_n, _) = self.tracks.iter()
+            .enumerate()
+            .filter_map(|(i, track)| track.events.get(track.cursor).map(|event| (i, event.tick)))
+            .min_by_key(|&(i, tick)| (tick, i))?;
+        let event = self.get_next_event(track_n)?;
+        Some((event.us, track_n, event))
+    }
+
+    /// rewinds every track to its first event
+    pub fn rewind(&mut self) {
+        for track in self.tracks.iter_mut() {
+            track.cursor = 0;
+        }
     }
 
     fn parse_tracks(smf: &Smf) -> Vec<MidiTrack> {
         let mut tracks = vec![];
 
-        for (i, track_midly) in smf.tracks.iter().enumerate() {
+        for track_midly in smf.tracks.iter() {
             let mut name = String::from("");
             let mut instrument = String::from("");
             let mut events = vec![];
-            let mut notes = vec![];
+            let notes = vec![];
             let cursor = 0;
+            let mut tick = 0;
 
             // parsing and storing track events
             for event in track_midly {
+                let delta_tick = event.delta.as_int();
+                tick += delta_tick as u64;
+                let mut channel = 0;
 
-                match event.kind {
+                let kind = match event.kind {
 
                     // check if the event is a meta message
-                    midly::TrackEventKind::Meta(info) => {
-                        match info {
-                            midly::MetaMessage::TrackName(track_name) => {
-                                for char in track_name {
-                                    name.push(*char as char);
-                                }
-                                events.push(MidiEvent {
-                                    kind: MidiEventKind::Other,
-                                    key: 0,
-                                    velocity: 0,
-                                    delta_tick: 0,
-                                    channel: 0,
-                                })
-                            }
-                            midly::MetaMessage::InstrumentName(inst_name) => {
-                                for char in inst_name {
-                                    instrument.push(*char as char);
-                                }
-                                events.push(MidiEvent {
-                                    kind: MidiEventKind::Other,
-                                    key: 0,
-                                    velocity: 0,
-                                    delta_tick: 0,
-                                    channel: 0,
-                                })
-                            }
-                            midly::MetaMessage::Tempo(tempo) => {
-                                events.push(MidiEvent {
-                                    kind: MidiEventKind::MetaSetTempo(tempo.as_int()),
-                                    key: 0,
-                                    velocity: 0,
-                                    delta_tick: 0,
-                                    channel: 0,
-                                })
-                            }
-                            _ => {
-                                events.push(MidiEvent {
-                                    kind: MidiEventKind::Other,
-                                    key: 0,
-                                    velocity: 0,
-                                    delta_tick: 0,
-                                    channel: 0,
-                                })
+                    midly::TrackEventKind::Meta(info) => match info {
+                        midly::MetaMessage::TrackName(track_name) => {
+                            name = text(track_name);
+                            MidiEventKind::MetaTrackName(name.clone())
+                        }
+                        midly::MetaMessage::InstrumentName(inst_name) => {
+                            instrument = text(inst_name);
+                            MidiEventKind::MetaInstrumentName(instrument.clone())
+                        }
+                        midly::MetaMessage::Tempo(tempo) => MidiEventKind::MetaSetTempo(tempo.as_int()),
+                        midly::MetaMessage::TimeSignature(numerator, denominator, clocks_per_click, notes_per_quarter) => {
+                            MidiEventKind::MetaTimeSignature {
+                                numerator,
+                                denominator: 1u8.checked_shl(denominator as u32).unwrap_or(0),
+                                clocks_per_click,
+                                notes_per_quarter,
                             }
                         }
-                    }
-
-                    // else check if event is of interest (note on or off for now)
-                    midly::TrackEventKind::Midi { channel, message } => {
+                        midly::MetaMessage::KeySignature(sharps, minor) => MidiEventKind::MetaKeySignature { sharps, minor },
+                        midly::MetaMessage::Text(data) => MidiEventKind::MetaText(text(data)),
+                        midly::MetaMessage::Lyric(data) => MidiEventKind::MetaLyric(text(data)),
+                        midly::MetaMessage::Marker(data) => MidiEventKind::MetaMarker(text(data)),
+                        midly::MetaMessage::EndOfTrack => MidiEventKind::MetaEndOfTrack,
+                        _ => MidiEventKind::Other,
+                    },
+
+                    midly::TrackEventKind::Midi { channel: midi_channel, message } => {
+                        channel = midi_channel.as_int();
                         match message {
+                            // by convention, a NoteOn message with 0 velocity should be treated as a NoteOff
+                            midly::MidiMessage::NoteOn { key, vel } if vel == 0 => {
+                                MidiEventKind::NoteOff { key: key.as_int(), velocity: 0 }
+                            }
                             midly::MidiMessage::NoteOn { key, vel } => {
-                                let mut kind = MidiEventKind::NoteOn;
-    
-                                // by convention, a NoteOn message with 0 velocity should be treated as a NoteOff
-                                if vel == 0 {
-                                    kind =MidiEventKind::NoteOff;
-                                }
-    
-                                events.push(MidiEvent {
-                                    kind,
-                                    key: key.as_int(),
-                                    velocity: vel.as_int(),
-                                    delta_tick: event.delta.as_int(),
-                                    channel: channel.as_int(),
-                                });
+                                MidiEventKind::NoteOn { key: key.as_int(), velocity: vel.as_int() }
                             }
                             midly::MidiMessage::NoteOff { key, vel } => {
-                                events.push(MidiEvent {
-                                    kind: MidiEventKind::NoteOff,
-                                    key: key.as_int(),
-                                    velocity: vel.as_int(),
-                                    delta_tick: event.delta.as_int(),
-                                    channel: channel.as_int(),
-                                })
+                                MidiEventKind::NoteOff { key: key.as_int(), velocity: vel.as_int() }
+                            }
+                            midly::MidiMessage::Aftertouch { key, vel } => {
+                                MidiEventKind::PolyAftertouch { key: key.as_int(), pressure: vel.as_int() }
                             }
-                            _ => {
-                                events.push(MidiEvent {
-                                    kind: MidiEventKind::Other,
-                                    key: 0,
-                                    velocity: 0,
-                                    delta_tick: event.delta.as_int(),
-                                    channel: channel.as_int(),
-                                })
+                            midly::MidiMessage::Controller { controller, value } => {
+                                MidiEventKind::ControlChange { controller: controller.as_int(), value: value.as_int() }
                             }
+                            midly::MidiMessage::ProgramChange { program } => MidiEventKind::ProgramChange(program.as_int()),
+                            midly::MidiMessage::ChannelAftertouch { vel } => MidiEventKind::ChannelAftertouch(vel.as_int()),
+                            midly::MidiMessage::PitchBend { bend } => MidiEventKind::PitchBend(bend.as_int()),
                         }
                     }
 
-                    _ => {
-                        events.push(MidiEvent {
-                            kind: MidiEventKind::Other,
-                            key: 0,
-                            velocity: 0,
-                            delta_tick: event.delta.as_int(),
-                            channel: 0,
-                        })
-                    }
-                }
+                    midly::TrackEventKind::SysEx(data) => MidiEventKind::SysEx(data.to_vec()),
+
+                    _ => MidiEventKind::Other,
+                };
+
+                events.push(MidiEvent {
+                    kind,
+                    channel,
+                    delta_tick,
+                    tick,
+                    us: 0,
+                });
             }
 
             tracks.push(MidiTrack {
@@ -225,35 +374,47 @@ impl MidiFile {
         tracks
     }
 
-    fn delta2us(&self, delta_ticks: u32) -> u32 {
-        self.tempo * delta_ticks / self.ticks_per_beat as u32
+    /// collects the tempo changes of every track and times every event with them
+    fn build_tempo_map(&mut self) {
+        let changes = self.tracks.iter()
+            .flat_map(|track| track.events.iter())
+            .filter_map(|event| match event.kind {
+                MidiEventKind::MetaSetTempo(tempo) => Some((event.tick, tempo)),
+                _ => None,
+            });
+        self.tempo_map = TempoMap::from_changes(self.tempo_map.timing(), changes);
+
+        for track in self.tracks.iter_mut() {
+            for event in track.events.iter_mut() {
+                event.us = self.tempo_map.tick2us(event.tick);
+            }
+        }
+    }
+
+    /// how the file's ticks relate to time
+    pub fn timing(&self) -> MidiTiming {
+        self.tempo_map.timing()
     }
 
-    fn midi2freq(note: u8) -> u32 {
-        pow(2, (note as usize - 69) / 12) * 440
+    /// converts between the file's ticks and microseconds
+    pub fn tempo_map(&self) -> &TempoMap {
+        &self.tempo_map
     }
 
     pub fn tracks(&self) -> &Vec<MidiTrack> {
         &self.tracks
     }
-    
-    pub fn list_tracks(&mut self) {
-        for (i, track) in self.tracks.iter().enumerate() {
-            let name = &track.name;
-            let n_messages = track.events.len();
-            println!("{i} - track '{name}': {n_messages} messages");
-        }
-    }
 
-    pub fn list_events(&mut self, track: usize) {
-        for (i, event) in self.tracks[track].events.iter().enumerate() {
-            println!("{:?}", event.kind);
-            // let name = &track.name;
-            // let n_messages = track.events.len();
-            // println!("{i} - track '{name}': {n_messages} messages");
-        }
+    /// notes of every track, track after track
+    pub fn notes(&self) -> impl Iterator<Item = &MidiNote> {
+        self.tracks.iter().flat_map(|track| track.notes.iter())
     }
 
+    /// per track and per channel summary of the file
+    pub fn stats(&self) -> MidiStats {
+        MidiStats::new(self)
+    }
+    
     // pub fn set_channels(&mut self) {
     //     for track in self.smf.tracks.iter() {
     //         for event in track {
@@ -270,41 +431,3 @@ impl MidiFile {
     //     self.smf.tracks[track].pop().unwrap()
     // }
 }
-
-pub fn play(){
-    let smf = Smf::parse(include_bytes!("../../musicas/ice_cap.mid")).unwrap();
-
-    // for track in smf.tracks {
-    //     for event in track {
-    //         if let TrackEventKind::Midi { channel, message } = event.kind {
-                
-    //         }
-    //         dbg!(event.kind);
-    //     }
-    // }
-    // for track in smf.tracks {
-    //     for event in track {
-    //         // Check if the event is a MIDI message
-    //         if let TrackEventKind::Midi { channel: _, message } = event.kind {
-    //             // Process the MIDI message
-    //             match message {
-    //                 // MidiMessage::NoteOn { key, vel } => {
-    //                 //     println!("Note On: key={}, velocity={}", key, vel);
-    //                 // }
-    //                 // MidiMessage::NoteOff { key, vel } => {
-    //                 //     println!("Note Off: key={}, velocity={}", key, vel);
-    //                 // }
-    //                 // Handle other MIDI message types as needed
-    //                 _ => {}
-    //             }
-    //         } else if let TrackEventKind::Meta(info) = event.kind {
-    //             dbg!(info);
-    //         }
-    //     }
-    // }
-
-    // dbg!(&smf.tracks[3][7].message());
-    // for (i, track) in smf.tracks.iter().enumerate() {
-    //     println!("track {} has {} events", i, track.len());
-    // }
-}
\ No newline at end of file
@@ -0,0 +1,142 @@
+use crate::config::EngineConfig;
+use crate::io::midi_reader::{MidiFile, MidiNote, MidiTrack};
+use crate::synth::drum_machine::DRUM_CHANNEL;
+
+/// what a set of notes asks of the synth
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NoteStats {
+    pub notes: usize,
+    pub lowest: Option<u8>,     // lowest key played
+    pub highest: Option<u8>,    // highest key played
+    pub polyphony: usize,       // most notes sounding at once
+    pub end_tick: u64,          // absolute tick the last note stops at
+    pub end_us: u64,            // absolute time the last note stops at in microseconds
+}
+
+impl NoteStats {
+
+    pub fn new<'a>(notes: impl IntoIterator<Item = &'a MidiNote>) -> Self {
+        let mut stats = Self::default();
+        // `(tick, 1)` for every note starting, `(tick, 0 or 2)` for every note stopping
+        let mut changes = vec![];
+
+        for note in notes {
+            stats.notes += 1;
+            stats.lowest = Some(stats.lowest.map_or(note.key, |key| key.min(note.key)));
+            stats.highest = Some(stats.highest.map_or(note.key, |key| key.max(note.key)));
+            stats.end_tick = stats.end_tick.max(note.end_tick());
+            stats.end_us = stats.end_us.max(note.end_us());
+            // a note stopping frees its voice for one starting on the same tick,
+            // unless it is a note of no length that still takes a voice
+            changes.push((note.start_tick, 1));
+            changes.push((note.end_tick(), if note.duration_ticks == 0 { 2 } else { 0 }));
+        }
+
+        changes.sort_unstable();
+        let mut sounding = 0;
+        for (_, change) in changes {
+            if change == 1 {
+                sounding += 1;
+                stats.polyphony = stats.polyphony.max(sounding);
+            } else {
+                sounding -= 1;
+            }
+        }
+        stats
+    }
+
+    /// lowest and highest key played
+    pub fn range(&self) -> Option<(u8, u8)> {
+        self.lowest.zip(self.highest)
+    }
+}
+
+/// summary of a track of a midi file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrackStats {
+    pub name: String,
+    pub instrument: String,
+    pub events: usize,
+    pub channels: Vec<u8>,      // midi channels the notes are on, in order
+    pub end_tick: u64,          // absolute tick of the last event
+    pub end_us: u64,            // absolute time of the last event in microseconds
+    pub notes: NoteStats,
+}
+
+impl TrackStats {
+
+    pub fn new(track: &MidiTrack) -> Self {
+        let mut channels: Vec<u8> = track.notes().iter().map(|note| note.channel).collect();
+        channels.sort_unstable();
+        channels.dedup();
+
+        Self {
+            name: track.name().to_string(),
+            instrument: track.instrument().to_string(),
+            events: track.events().len(),
+            channels,
+            end_tick: track.end_tick(),
+            end_us: track.end_us(),
+            notes: NoteStats::new(track.notes()),
+        }
+    }
+}
+
+/// summary of a midi channel across every track, which is what a synth channel plays
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChannelStats {
+    pub channel: u8,
+    pub notes: NoteStats,
+}
+
+/// summary of a midi file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MidiStats {
+    pub tracks: Vec<TrackStats>,
+    pub channels: Vec<ChannelStats>,    // channels playing notes, in order
+    pub end_tick: u64,                  // absolute tick of the last event
+    pub end_us: u64,                    // length of the file in microseconds
+}
+
+impl MidiStats {
+
+    pub fn new(file: &MidiFile) -> Self {
+        let tracks: Vec<TrackStats> = file.tracks().iter().map(TrackStats::new).collect();
+
+        let channels = (0..16)
+            .map(|channel| ChannelStats {
+                channel,
+                notes: NoteStats::new(file.notes().filter(|note| note.channel == channel)),
+            })
+            .filter(|stats| stats.notes.notes > 0)
+            .collect();
+
+        Self {
+            end_tick: tracks.iter().map(|track| track.end_tick).max().unwrap_or(0),
+            end_us: tracks.iter().map(|track| track.end_us).max().unwrap_or(0),
+            tracks,
+            channels,
+        }
+    }
+
+    /// number of notes across every track
+    pub fn notes(&self) -> usize {
+        self.channels.iter().map(|stats| stats.notes.notes).sum()
+    }
+
+    /// most notes sounding at once on a single channel
+    pub fn polyphony(&self) -> usize {
+        self.channels.iter().map(|stats| stats.notes.polyphony).max().unwrap_or(0)
+    }
+
+    /// whether a synth built with `config` can play every note, each midi channel
+    /// being played by the synth channel of the same number. the drum channel's
+    /// keys hit drums rather than voices, so its polyphony doesn't count
+    pub fn fits(&self, config: &EngineConfig) -> bool {
+        self.channels.iter().all(|stats| {
+            let channel_n = stats.channel as usize;
+            channel_n < config.channels_max
+                && (channel_n == DRUM_CHANNEL || stats.notes.polyphony <= config.voices_max)
+        })
+    }
+}
@@ -0,0 +1,285 @@
+use std::path::Path;
+
+use midly::num::{u15, u24, u28, u4, u7};
+use midly::{Format, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind};
+
+use crate::io::midi_reader::MidiEventKind;
+use crate::io::tempo_map::{MidiTiming, TempoMap};
+use crate::io::player::PlayerEvent;
+use crate::io::score_reader::{Pitch, Score};
+use crate::synth::tuning::{freq2midi, A4_FREQ};
+use crate::{Error, Result};
+
+/// ticks per beat of exported files
+pub const TICKS_PER_BEAT: u16 = 480;
+
+/// channels a midi file can hold
+const MIDI_CHANNELS: usize = 16;
+
+/// a note starting or stopping, as recorded for export
+#[derive(Clone, Copy, Debug)]
+struct Note {
+    us: u64,
+    channel: usize,
+    key: u8,
+    velocity: u8,       // 0 for note off
+}
+
+/// records timed events and writes them out as a Type 1 standard midi file
+///
+/// the first track only holds the tempo map, followed by one track per synth
+/// channel that played anything, on the midi channel of the same number.
+/// notes given as frequencies are written as the nearest 12 tone equal tempered key
+#[derive(Clone, Debug)]
+pub struct MidiWriter {
+    notes: Vec<Note>,
+    tempos: Vec<(u64, u32)>,    // tempo changes in microseconds per beat and when they happen in microseconds
+    held: Vec<(usize, u8)>,     // channel and key of notes still sounding
+}
+
+impl Default for MidiWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MidiWriter {
+
+    /// empty recording at 120 beats per minute
+    pub fn new() -> Self {
+        Self {
+            notes: vec![],
+            tempos: vec![],
+            held: vec![],
+        }
+    }
+
+    /// records every note of `score` along with its tempo changes
+    pub fn from_score(score: &mut Score) -> Self {
+        let mut writer = Self::new();
+        for (us, bpm) in score.tempos() {
+            writer.set_tempo(us, bpm);
+        }
+
+        score.rewind();
+        while let Some(event) = score.next_event() {
+            let key = match event.pitch {
+                Pitch::Key(key) => key,
+                Pitch::Freq(freq) => freq2midi(freq, A4_FREQ),
+            };
+            if event.on {
+                writer.note_on(event.us, event.channel, key, event.velocity);
+            } else {
+                writer.note_off(event.us, event.channel, key);
+            }
+        }
+        score.rewind();
+        writer
+    }
+
+    /// changes the tempo to `bpm` beats per minute from `us` microseconds on
+    pub fn set_tempo(&mut self, us: u64, bpm: f64) {
+        let tempo = (60_000_000. / bpm).round().clamp(1., 0xFF_FFFF as f64) as u32;
+        self.tempos.retain(|&(at, _)| at != us);
+        self.tempos.push((us, tempo));
+        self.tempos.sort_by_key(|&(at, _)| at);
+    }
+
+    /// records `event` happening at `us` microseconds, ignoring events that don't
+    /// start or stop notes
+    pub fn record(&mut self, us: u64, event: &PlayerEvent) {
+        match *event {
+            PlayerEvent::MidiMessage(ref event) => {
+                let channel = event.channel() as usize;
+                match *event.kind() {
+                    MidiEventKind::NoteOn { key, velocity } => self.note_on(us, channel, key, velocity),
+                    MidiEventKind::NoteOff { key, .. } => self.note_off(us, channel, key),
+                    _ => {}
+                }
+            }
+            PlayerEvent::KeyOn { channel, key, velocity, .. } => self.note_on(us, channel, key, velocity),
+            PlayerEvent::KeyOff { channel, key } => self.note_off(us, channel, key),
+            PlayerEvent::NoteOn { channel, freq, velocity, .. } => self.note_on(us, channel, freq2midi(freq, A4_FREQ), velocity),
+            PlayerEvent::NoteOff { channel, freq } => self.note_off(us, channel, freq2midi(freq, A4_FREQ)),
+            PlayerEvent::AllNotesOff { channel } => {
+                let keys: Vec<u8> = self.held.iter().filter(|held| held.0 == channel).map(|held| held.1).collect();
+                for key in keys {
+                    self.note_off(us, channel, key);
+                }
+            }
+            PlayerEvent::KeyPress(_) => {}
+        }
+    }
+
+    pub fn note_on(&mut self, us: u64, channel: usize, key: u8, velocity: u8) {
+        // midi can't hold the same key twice on a channel
+        if self.held.contains(&(channel, key)) {
+            self.note_off(us, channel, key);
+        }
+        self.held.push((channel, key));
+        self.notes.push(Note { us, channel, key, velocity: velocity.clamp(1, 127) });
+    }
+
+    pub fn note_off(&mut self, us: u64, channel: usize, key: u8) {
+        if let Some(i) = self.held.iter().position(|&held| held == (channel, key)) {
+            self.held.remove(i);
+            self.notes.push(Note { us, channel, key, velocity: 0 });
+        }
+    }
+
+    /// number of notes recorded
+    pub fn len(&self) -> usize {
+        self.notes.iter().filter(|note| note.velocity > 0).count()
+    }
+
+    /// whether no notes were recorded
+    pub fn is_empty(&self) -> bool {
+        !self.notes.iter().any(|note| note.velocity > 0)
+    }
+
+    /// tempo map of the recording's tempo changes
+    pub fn tempo_map(&self) -> TempoMap {
+        let mut map = TempoMap::new(MidiTiming::Metrical(TICKS_PER_BEAT));
+        for &(us, tempo) in self.tempos.iter() {
+            map.insert(map.us2tick(us), tempo);
+        }
+        map
+    }
+
+    /// turns `(tick, kind)` pairs in tick order into a track ending after the last one
+    fn track<'a>(events: impl Iterator<Item = (u64, TrackEventKind<'a>)>) -> Vec<TrackEvent<'a>> {
+        let mut track = vec![];
+        let mut last = 0;
+        for (tick, kind) in events {
+            // converting sorted times to ticks keeps them in order, but a delta can't go back anyway
+            let tick = tick.max(last);
+            track.push(TrackEvent { delta: u28::new((tick - last) as u32), kind });
+            last = tick;
+        }
+        track.push(TrackEvent { delta: u28::new(0), kind: TrackEventKind::Meta(MetaMessage::EndOfTrack) });
+        track
+    }
+
+    /// the recording as a Type 1 standard midi file, notes still sounding stopping
+    /// with the last one
+    ///
+    /// fails if anything was played on a channel past the 16 midi has
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        if let Some(note) = self.notes.iter().find(|note| note.channel >= MIDI_CHANNELS) {
+            return Err(Error::MidiWrite(format!("channel {} is past the last midi channel", note.channel)));
+        }
+        let end = self.notes.iter().map(|note| note.us).max().unwrap_or(0);
+        let mut notes = self.notes.clone();
+        notes.extend(self.held.iter().map(|&(channel, key)| Note { us: end, channel, key, velocity: 0 }));
+        // stable, so a note stopping and one starting at the same time keep their order
+        notes.sort_by_key(|note| note.us);
+
+        let map = self.tempo_map();
+        let tempos = map.changes()
+            .iter()
+            .map(|change| (change.tick, TrackEventKind::Meta(MetaMessage::Tempo(u24::new(change.tempo)))));
+        let mut tracks = vec![Self::track(tempos)];
+
+        let mut channels: Vec<usize> = notes.iter().map(|note| note.channel).collect();
+        channels.sort_unstable();
+        channels.dedup();
+        for channel in channels {
+            let events = notes.iter()
+                .filter(|note| note.channel == channel)
+                .map(|note| {
+                    let channel = u4::new(channel as u8);
+                    let key = u7::new(note.key & 0x7F);
+                    let message = match note.velocity {
+                        0 => MidiMessage::NoteOff { key, vel: u7::new(0) },
+                        velocity => MidiMessage::NoteOn { key, vel: u7::new(velocity) },
+                    };
+                    (map.us2tick(note.us), TrackEventKind::Midi { channel, message })
+                });
+            tracks.push(Self::track(events));
+        }
+
+        let header = Header::new(Format::Parallel, Timing::Metrical(u15::new(TICKS_PER_BEAT)));
+        let mut smf = Smf::new(header);
+        smf.tracks = tracks;
+        let mut bytes = vec![];
+        smf.write_std(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// writes the recording to a standard midi file
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(path, self.to_bytes()?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EngineConfig;
+    use crate::io::midi_reader::MidiFile;
+    use crate::io::score_reader::ScoreTrack;
+
+    /// time, channel, whether it starts and key of every note event of `bytes`
+    fn read_notes(bytes: &[u8]) -> Vec<(u64, u8, bool, u8)> {
+        let mut file = MidiFile::new(bytes).unwrap();
+        let mut notes = vec![];
+        while let Some((us, event)) = file.next_event() {
+            match *event.kind() {
+                MidiEventKind::NoteOn { key, velocity } if velocity > 0 => notes.push((us, event.channel(), true, key)),
+                MidiEventKind::NoteOn { key, .. } | MidiEventKind::NoteOff { key, .. } => {
+                    notes.push((us, event.channel(), false, key))
+                }
+                _ => {}
+            }
+        }
+        notes
+    }
+
+    #[test]
+    fn scores_survive_a_round_trip() {
+        let config = EngineConfig::default();
+        let lead = ScoreTrack::parse("C4 1\ntempo 60\nE4 1/2\nr 1\n1 440 0\n0 440 250000", 0, &config).unwrap();
+        let bass = ScoreTrack::parse("C2 2\nchannel 3\nG2 1", 1, &config).unwrap();
+        let mut score = Score::new(vec![lead, bass]);
+
+        let writer = MidiWriter::from_score(&mut score);
+        assert_eq!(writer.len(), 5);
+        assert!(!writer.is_empty());
+
+        let mut expected = vec![];
+        score.rewind();
+        while let Some(event) = score.next_event() {
+            let key = match event.pitch {
+                Pitch::Key(key) => key,
+                Pitch::Freq(freq) => freq2midi(freq, A4_FREQ),
+            };
+            expected.push((event.us, event.channel as u8, event.on, key));
+        }
+        // tracks are read one after another at the same time, so compare in a set order
+        let mut notes = read_notes(&writer.to_bytes().unwrap());
+        notes.sort_by_key(|&(us, channel, on, key)| (us, channel, on, key));
+        expected.sort_by_key(|&(us, channel, on, key)| (us, channel, on, key));
+        assert_eq!(notes, expected);
+        assert_eq!(notes.last(), Some(&(2_250_000, 0, false, 69)));
+    }
+
+    #[test]
+    fn empty_recordings_agree_on_their_length() {
+        let mut writer = MidiWriter::new();
+        assert!(writer.is_empty());
+        assert_eq!(writer.len(), 0);
+        writer.note_off(0, 0, 60);
+        assert!(writer.is_empty());
+        writer.note_on(0, 0, 60, 100);
+        assert_eq!(writer.len(), 1);
+        assert!(!writer.is_empty());
+    }
+
+    #[test]
+    fn channels_past_midi_are_refused() {
+        let mut writer = MidiWriter::new();
+        writer.note_on(0, 16, 60, 100);
+        assert!(writer.to_bytes().is_err());
+    }
+}
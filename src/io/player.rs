@@ -1,104 +1,503 @@
-use std::{time::Duration, io, thread};
-use console::Term;
 use termion::event::{Event, Key};
 use termion::input::TermRead;
 use termion::raw::IntoRawMode;
 use termion::async_stdin;
-use std::io::{stdin, stdout, Write};
-use crate::synth::voice::Voice;
+use std::io::{stdout, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::config::EngineConfig;
 use crate::io::audio_out::AudioOut;
+use crate::io::audio_thread::AudioThread;
 use crate::synth::Synth;
-use crate::{AMPLITUDE_MAX, SAMPLE_RATE};
+use crate::{Error, Result};
 
-use crate::io::midi_reader::{MidiFile, MidiEvent};
+use crate::io::midi_input::MidiInput;
+use crate::io::midi_reader::{MidiFile, MidiEvent, MidiEventKind};
+use crate::io::midi_writer::MidiWriter;
+use crate::io::score_reader::{Pitch, Score};
 
+/// duty cycle used for every note played from a midi file
+const MIDI_DUTY: f32 = 0.5;
+
+/// velocity of notes played from the computer keyboard, which can't tell how hard keys are struck
+const KEYBOARD_VELOCITY: u8 = 127;
+
+#[derive(Clone, Debug)]
 pub enum PlayerEvent {
     KeyPress(char),
     MidiMessage(MidiEvent),
+    KeyOn { channel: usize, key: u8, velocity: u8, duty: f32 },
+    KeyOff { channel: usize, key: u8 },
+    NoteOn { channel: usize, freq: f32, velocity: u8, duty: f32 },
+    NoteOff { channel: usize, freq: f32 },
+    AllNotesOff { channel: usize },
     // Add other event types as needed
 }
 
+impl PlayerEvent {
+
+    /// plays the event on `synth`
+    pub fn apply(self, synth: &mut Synth) {
+        match self {
+            PlayerEvent::MidiMessage(event) => {
+                let channel = event.channel() as usize;
+                match *event.kind() {
+                    MidiEventKind::NoteOn { key, velocity } => synth.key_on_velocity(key, velocity, MIDI_DUTY, channel),
+                    MidiEventKind::NoteOff { key, .. } => synth.key_off(key, channel),
+                    MidiEventKind::PitchBend(bend) => synth.pitch_bend(bend, channel),
+                    MidiEventKind::ProgramChange(program) => synth.program_change(program, channel),
+                    MidiEventKind::ControlChange { controller, value } => synth.control_change(controller, value, channel),
+                    _ => {}
+                }
+            }
+            PlayerEvent::KeyOn { channel, key, velocity, duty } => synth.key_on_velocity(key, velocity, duty, channel),
+            PlayerEvent::KeyOff { channel, key } => synth.key_off(key, channel),
+            PlayerEvent::NoteOn { channel, freq, velocity, duty } => synth.note_on_velocity(freq, velocity, duty, channel),
+            PlayerEvent::NoteOff { channel, freq } => synth.note_off(freq, channel),
+            PlayerEvent::AllNotesOff { channel } => synth.all_notes_off(channel),
+            PlayerEvent::KeyPress(_) => {}
+        }
+    }
+}
+
 pub trait PlayerMode {
-    fn audio_out(&mut self, sample: u8);
     fn update(&mut self);
     fn process_event(&mut self, event: PlayerEvent);
+    fn is_finished(&self) -> bool;
+    fn drain(&mut self) -> Result<()>;
     // fn start(&mut self);
     // fn stop(&mut self);
 }
 
+/// how long a key sounds after being pressed, unless the terminal repeats it
+const KEY_HOLD_FIRST: Duration = Duration::from_millis(600);
+/// how long a repeating key keeps sounding after its last repeat
+const KEY_HOLD_REPEAT: Duration = Duration::from_millis(120);
+
+/// tracker style layout, two octaves on the two lower rows of a qwerty keyboard,
+/// returns the semitone above the base octave's C
+fn char2semitone(ch: char) -> Option<u8> {
+    let semitone = match ch {
+        'z' => 0, 's' => 1, 'x' => 2, 'd' => 3, 'c' => 4, 'v' => 5,
+        'g' => 6, 'b' => 7, 'h' => 8, 'n' => 9, 'j' => 10, 'm' => 11,
+        'q' => 12, '2' => 13, 'w' => 14, '3' => 15, 'e' => 16, 'r' => 17,
+        '5' => 18, 't' => 19, '6' => 20, 'y' => 21, '7' => 22, 'u' => 23,
+        _ => return None,
+    };
+    Some(semitone)
+}
+
+/// a key that is sounding
+struct HeldKey {
+    ch: char,
+    channel: usize,
+    key: u8,
+    last: Instant,      // last time the terminal reported the key
+    repeated: bool,     // whether the terminal started repeating the key
+}
+
+/// plays the synth from the computer keyboard
+///
+/// terminals don't report key releases, so a note stops once its key stops
+/// auto repeating
 pub struct KeyboardPlayer {
-    output: AudioOut,
-    synth: Synth,
+    audio: AudioThread,
+    octave: u8,
+    duty: f32,
+    channel: usize,
+    channels_max: usize,
+    held: Vec<HeldKey>,
+    recording: Option<(PathBuf, MidiWriter)>,
 }
 
 impl KeyboardPlayer {
 
-    pub fn new() -> Self {
+    /// opens the sound card, the synth running at whatever rate the card accepted
+    pub fn new(config: &EngineConfig) -> Result<Self> {
+        let output = AudioOut::new(config)?;
+        let synth = Synth::new(output.config());
+        Ok(Self::with_output(synth, output))
+    }
+
+    /// plays `synth` into `output` instead of the default sound card
+    ///
+    /// every channel the keyboard cycles through plays notes, none of them drums
+    pub fn with_output(mut synth: Synth, output: AudioOut) -> Self {
+        synth.set_drum_channel(None);
+        let channels_max = synth.config().channels_max;
         Self {
-            output: AudioOut::new(),
-            synth: Synth::new(),
+            audio: AudioThread::spawn(synth, output),
+            octave: 4,
+            duty: 0.5,
+            channel: 0,
+            channels_max,
+            held: vec![],
+            recording: None,
         }
     }
-}
 
-impl PlayerMode for KeyboardPlayer {
-    fn audio_out(&mut self, sample: u8) {
-        self.output.audio_out(sample);
+    /// records the performance, writing it to the midi file at `path` when done
+    pub fn record_to(mut self, path: impl AsRef<Path>) -> Self {
+        self.recording = Some((path.as_ref().to_path_buf(), MidiWriter::new()));
+        self
+    }
+
+    /// one line summary of the keyboard's settings
+    pub fn status(&self) -> String {
+        format!("octave {}  duty {:.2}  channel {}", self.octave, self.duty, self.channel + 1)
+    }
+
+    fn press(&mut self, ch: char, semitone: u8) {
+        if let Some(held) = self.held.iter_mut().find(|held| held.ch == ch) {
+            held.last = Instant::now();
+            held.repeated = true;
+            return;
+        }
+        let key = (self.octave + 1) * 12 + semitone;
+        if key > 127 {
+            return;
+        }
+        self.send(PlayerEvent::KeyOn { channel: self.channel, key, velocity: KEYBOARD_VELOCITY, duty: self.duty });
+        self.held.push(HeldKey {
+            ch,
+            channel: self.channel,
+            key,
+            last: Instant::now(),
+            repeated: false,
+        });
+    }
+
+    fn release_all(&mut self) {
+        for held in std::mem::take(&mut self.held) {
+            self.send(PlayerEvent::KeyOff { channel: held.channel, key: held.key });
+        }
     }
 
+    /// plays `event` right away, recording it if asked to
+    fn send(&mut self, event: PlayerEvent) {
+        if let Some((_, recording)) = &mut self.recording {
+            let us = self.audio.now() * 1_000_000 / self.audio.sample_rate() as u64;
+            recording.record(us, &event);
+        }
+        self.audio.send(event);
+    }
+}
+
+impl PlayerMode for KeyboardPlayer {
+    /// releases keys the terminal stopped repeating
     fn update(&mut self) {
-        
+        let (sounding, released) = std::mem::take(&mut self.held).into_iter().partition(|held| {
+            let hold = if held.repeated { KEY_HOLD_REPEAT } else { KEY_HOLD_FIRST };
+            held.last.elapsed() < hold
+        });
+        self.held = sounding;
+        for held in released {
+            self.send(PlayerEvent::KeyOff { channel: held.channel, key: held.key });
+        }
     }
 
     fn process_event(&mut self, event: PlayerEvent) {
-        
+        let PlayerEvent::KeyPress(ch) = event else {
+            self.send(event);
+            return;
+        };
+
+        if let Some(semitone) = char2semitone(ch) {
+            self.press(ch, semitone);
+            return;
+        }
+
+        match ch {
+            '-' => self.octave = self.octave.saturating_sub(1),
+            '=' => self.octave = (self.octave + 1).min(8),
+            '[' => self.duty = (self.duty - 0.05).max(0.05),
+            ']' => self.duty = (self.duty + 0.05).min(0.5),
+            ',' => self.channel = (self.channel + self.channels_max - 1) % self.channels_max,
+            '.' => self.channel = (self.channel + 1) % self.channels_max,
+            ' ' => {
+                self.release_all();
+                self.send(PlayerEvent::AllNotesOff { channel: self.channel });
+            }
+            _ => return,
+        }
+        print!("\r{}\x1b[K", self.status());
+        let _ = stdout().flush();
+    }
+
+    /// only once the audio thread stopped, the keyboard otherwise playing until Esc
+    fn is_finished(&self) -> bool {
+        !self.audio.is_running()
+    }
+
+    fn drain(&mut self) -> Result<()> {
+        self.release_all();
+        self.audio.stop()?;
+        match self.recording.take() {
+            Some((path, recording)) => recording.save(path),
+            None => Ok(()),
+        }
     }
 }
 
 
+/// microseconds as samples at `sample_rate`
+fn us2samples(us: u64, sample_rate: u32) -> u64 {
+    us * sample_rate as u64 / 1_000_000
+}
+
+/// queues `next` and the events `read` returns after it until the queue is full,
+/// letting a sequenced audio thread render up to the first event still missing
+///
+/// events are timed in samples, `end` is moved past the last event queued
+fn queue_events(
+    audio: &mut AudioThread,
+    next: &mut Option<(u64, PlayerEvent)>,
+    end: &mut u64,
+    mut read: impl FnMut() -> Option<(u64, PlayerEvent)>,
+) {
+    while let Some((sample, event)) = next.take() {
+        if let Err(event) = audio.schedule(sample, event) {
+            *next = Some((sample, event));
+            audio.advance(sample);
+            thread::sleep(Duration::from_millis(1));
+            return;
+        }
+        // the last event still gets a sample to sound on
+        *end = sample + 1;
+        *next = read();
+    }
+    audio.advance(*end);
+    thread::sleep(Duration::from_millis(1));
+}
+
+/// sequences the events of a `MidiFile` into a `Synth`, queueing them ahead of a
+/// sequenced `AudioThread`
 pub struct MidiPlayer {
-    output: AudioOut,
-    synth: Synth,
+    audio: AudioThread,
     file: MidiFile,
-    pointers: Vec<usize>,
+    next: Option<(u64, PlayerEvent)>,   // next event not queued yet and its time in samples
+    end: u64,                           // samples to render once every event is queued
 }
 
 impl MidiPlayer {
 
-    pub fn new(file: MidiFile) -> Self {
-        let pointers = vec![0; file.tracks().len()];
-        Self {
-            output: AudioOut::new(),
-            synth: Synth::new(),
+    /// plays `file` on the sound card, timed by the rate the card accepted
+    pub fn new(file: MidiFile, config: &EngineConfig) -> Result<Self> {
+        Ok(Self::with_output(file, AudioOut::new(config)?))
+    }
+
+    /// plays `file` into `output` instead of the default sound card
+    pub fn with_output(file: MidiFile, output: AudioOut) -> Self {
+        let synth = Synth::new(output.config());
+        Self::with_synth(file, synth, output)
+    }
+
+    /// plays `file` on an already set up `synth`, e.g. with its channels retuned
+    pub fn with_synth(file: MidiFile, synth: Synth, output: AudioOut) -> Self {
+        let mut player = Self {
+            audio: AudioThread::spawn_sequenced(synth, output),
             file,
-            pointers,
-        }
+            next: None,
+            end: 0,
+        };
+        let sample_rate = player.audio.sample_rate();
+        player.next = midi2event(&mut player.file, sample_rate);
+        player
+    }
+
+    /// times the sound card ran out of samples during playback
+    pub fn underruns(&self) -> u64 {
+        self.audio.underruns()
     }
 }
 
+/// next event of `file` as a `PlayerEvent` timed in samples
+fn midi2event(file: &mut MidiFile, sample_rate: u32) -> Option<(u64, PlayerEvent)> {
+    let (us, event) = file.next_event()?;
+    Some((us2samples(us, sample_rate), PlayerEvent::MidiMessage(event)))
+}
+
 impl PlayerMode for MidiPlayer {
-    fn audio_out(&mut self, sample: u8) {
-        self.output.audio_out(sample);
+    fn update(&mut self) {
+        let sample_rate = self.audio.sample_rate();
+        let file = &mut self.file;
+        queue_events(&mut self.audio, &mut self.next, &mut self.end, || midi2event(file, sample_rate));
+    }
+
+    fn process_event(&mut self, event: PlayerEvent) {
+        self.audio.send(event);
+    }
+
+    /// once every event has been rendered, or the audio thread stopped early, `drain`
+    /// then reporting why
+    fn is_finished(&self) -> bool {
+        !self.audio.is_running() || (self.next.is_none() && self.audio.now() >= self.end)
     }
 
+    fn drain(&mut self) -> Result<()> {
+        self.audio.stop()
+    }
+}
+
+/// plays a text score, one track per channel, queueing its events ahead of a
+/// sequenced `AudioThread`
+pub struct ScorePlayer {
+    audio: AudioThread,
+    score: Score,
+    next: Option<(u64, PlayerEvent)>,   // next event not queued yet and its time in samples
+    end: u64,                           // samples to render once every event is queued
+}
+
+impl ScorePlayer {
+
+    /// plays `score` on the sound card, timed by the rate the card accepted
+    pub fn new(score: Score, config: &EngineConfig) -> Result<Self> {
+        Ok(Self::with_output(score, AudioOut::new(config)?))
+    }
+
+    /// plays `score` into `output` instead of the default sound card
+    pub fn with_output(score: Score, output: AudioOut) -> Self {
+        let synth = Synth::new(output.config());
+        Self::with_synth(score, synth, output)
+    }
+
+    /// plays `score` on an already set up `synth`
+    ///
+    /// scores only hold pitched notes, so no channel plays drums
+    pub fn with_synth(score: Score, mut synth: Synth, output: AudioOut) -> Self {
+        synth.set_drum_channel(None);
+        let mut player = Self {
+            audio: AudioThread::spawn_sequenced(synth, output),
+            score,
+            next: None,
+            end: 0,
+        };
+        let sample_rate = player.audio.sample_rate();
+        player.next = score2event(&mut player.score, sample_rate);
+        player
+    }
+
+    /// times the sound card ran out of samples during playback
+    pub fn underruns(&self) -> u64 {
+        self.audio.underruns()
+    }
+}
+
+/// next event of `score` as a `PlayerEvent` timed in samples
+fn score2event(score: &mut Score, sample_rate: u32) -> Option<(u64, PlayerEvent)> {
+    let event = score.next_event()?;
+    let (channel, velocity, duty) = (event.channel, event.velocity, event.duty);
+    let player_event = match (event.pitch, event.on) {
+        (Pitch::Key(key), true) => PlayerEvent::KeyOn { channel, key, velocity, duty },
+        (Pitch::Key(key), false) => PlayerEvent::KeyOff { channel, key },
+        (Pitch::Freq(freq), true) => PlayerEvent::NoteOn { channel, freq, velocity, duty },
+        (Pitch::Freq(freq), false) => PlayerEvent::NoteOff { channel, freq },
+    };
+    Some((us2samples(event.us, sample_rate), player_event))
+}
+
+impl PlayerMode for ScorePlayer {
     fn update(&mut self) {
-        let file = &mut self.file;
-        for i in 0..file.tracks().len() {
-            let event = file.get_next_event(i);
-            while event.delta_tick() == 0 {
-                self.process_event(PlayerEvent::MidiMessage(event));
+        let sample_rate = self.audio.sample_rate();
+        let score = &mut self.score;
+        queue_events(&mut self.audio, &mut self.next, &mut self.end, || score2event(score, sample_rate));
+    }
+
+    fn process_event(&mut self, event: PlayerEvent) {
+        self.audio.send(event);
+    }
+
+    /// once every event has been rendered, or the audio thread stopped early, `drain`
+    /// then reporting why
+    fn is_finished(&self) -> bool {
+        !self.audio.is_running() || (self.next.is_none() && self.audio.now() >= self.end)
+    }
+
+    fn drain(&mut self) -> Result<()> {
+        self.audio.stop()
+    }
+}
+
+/// plays midi events as they come from a `MidiInput`, such as a hardware controller
+/// connected through the ALSA sequencer
+pub struct MidiInputPlayer {
+    audio: AudioThread,
+    input: Box<dyn MidiInput>,
+    error: Option<Error>,   // why the input stopped being read, reported on drain
+}
+
+impl MidiInputPlayer {
+
+    /// plays `input` on the sound card
+    pub fn new(input: Box<dyn MidiInput>, config: &EngineConfig) -> Result<Self> {
+        let output = AudioOut::new(config)?;
+        let synth = Synth::new(output.config());
+        Ok(Self::with_output(input, synth, output))
+    }
+
+    /// plays `input` on `synth` into `output` instead of the default sound card
+    pub fn with_output(input: Box<dyn MidiInput>, synth: Synth, output: AudioOut) -> Self {
+        Self {
+            audio: AudioThread::spawn(synth, output),
+            input,
+            error: None,
+        }
+    }
+
+    /// times the sound card ran out of samples during playback
+    pub fn underruns(&self) -> u64 {
+        self.audio.underruns()
+    }
+}
+
+impl PlayerMode for MidiInputPlayer {
+    /// plays every event waiting, resting a little when there are none
+    fn update(&mut self) {
+        loop {
+            match self.input.poll() {
+                Ok(Some(event)) => self.audio.send(PlayerEvent::MidiMessage(event)),
+                Ok(None) => break,
+                Err(err) => {
+                    self.error = Some(err);
+                    return;
+                }
             }
         }
+        thread::sleep(Duration::from_millis(1));
     }
 
     fn process_event(&mut self, event: PlayerEvent) {
+        self.audio.send(event);
+    }
+
+    fn is_finished(&self) -> bool {
+        self.error.is_some() || self.input.is_closed() || !self.audio.is_running()
+    }
 
+    fn drain(&mut self) -> Result<()> {
+        self.audio.stop()?;
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
     }
 }
 
+const KEYBOARD_HELP: &str = "\
+notes       z s x d c v g b h n j m  /  q 2 w 3 e r 5 t 6 y 7 u\r
+octave      - =\r
+duty        [ ]\r
+channel     , .\r
+all off     space\r
+quit        esc\r
+";
+
 pub enum Mode {
     Keyboard(KeyboardPlayer),
     Midi(MidiPlayer),
+    Score(ScorePlayer),
+    MidiInput(MidiInputPlayer),
 }
 
 pub struct Player {
@@ -117,47 +516,49 @@ impl Player {
     //     self.pcm.drain().unwrap();
     // }
 
-    pub fn audio_out(&mut self, sample: u8) {
-        self.mode.audio_out(sample);
+    /// updates the player until it is finished, then drains the output
+    pub fn play(&mut self) -> Result<()> {
+        while !self.mode.is_finished() {
+            self.mode.update();
+        }
+        self.mode.drain()
     }
 
-    pub fn keyboard_player(&mut self) {
-        let mut voice = Voice::new(440, 0.5);
-
+    /// updates the player until it is finished or Esc is pressed, then drains the output
+    pub fn play_live(&mut self) -> Result<()> {
         let mut stdin = async_stdin().events();
-        let mut stdout = stdout().into_raw_mode().unwrap();
-        let mut key_pressed = false;
-
-        write!(stdout, "Press 'W' to play sound, 'Q' to exit...\r\n").unwrap();
-        stdout.flush().unwrap();
-
-        loop {
-            let event = stdin.next();
+        let _raw = stdout().into_raw_mode()?;
 
-            if let Some(Ok(Event::Key(Key::Char('q')))) = event {
-                write!(stdout, "Exiting...\r\n").unwrap();
-                stdout.flush().unwrap();
+        while !self.mode.is_finished() {
+            if let Some(Ok(Event::Key(Key::Esc | Key::Ctrl('c')))) = stdin.next() {
                 break;
             }
+            self.mode.update();
+        }
+        self.mode.drain()
+    }
 
-            if let Some(Ok(Event::Key(Key::Char('w')))) = event {
-                key_pressed = true;
-                write!(stdout, "Key pressed: W\r\n").unwrap();
-                // self.output.audio_out(voice.out());
-            }
+    /// feeds terminal key presses to the player until Esc is pressed
+    pub fn keyboard_player(&mut self) -> Result<()> {
+        let mut stdin = async_stdin().events();
+        let mut stdout = stdout().into_raw_mode()?;
 
-            if let Some(Ok(Event::Key(Key::Char(ch)))) = event {
-                write!(stdout, "Key pressed: {}\r\n", ch).unwrap();
-            }
+        write!(stdout, "{KEYBOARD_HELP}")?;
+        stdout.flush()?;
 
-            if key_pressed {
-                // self.output.audio_out(voice.out());
-                // key_pressed = false;
+        while !self.mode.is_finished() {
+            match stdin.next() {
+                Some(Ok(Event::Key(Key::Esc))) | Some(Ok(Event::Key(Key::Ctrl('c')))) => break,
+                Some(Ok(Event::Key(Key::Char(ch)))) => self.mode.process_event(PlayerEvent::KeyPress(ch)),
+                Some(_) => {}
+                None => thread::sleep(Duration::from_millis(1)),
             }
-
-            stdout.flush().unwrap();
-            // sleep(Duration::from_millis(50)); // Add a small delay to reduce CPU usage
+            self.mode.update();
         }
+
+        write!(stdout, "\r\nExiting...\r\n")?;
+        stdout.flush()?;
+        self.mode.drain()
     }
 
     pub fn update(&mut self) {
@@ -165,7 +566,46 @@ impl Player {
         //TODO: both midi and keyboard players should be updated from the main instead of containing their own loops
     }
 
-    pub fn drain(&mut self) {
-        // self.output.drain();
+    pub fn drain(&mut self) -> Result<()> {
+        self.mode.drain()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, File};
+    use wav::BitDepth;
+    use crate::io::midi_input::MemoryInput;
+    use crate::io::wav_writer::WavSink;
+
+    #[test]
+    fn midi_input_is_rendered_until_it_closes() {
+        let config = EngineConfig::default();
+        let path = std::env::temp_dir().join(format!("obs_midi_in_{}.wav", std::process::id()));
+        let silence = Synth::new(&config).get_sample();
+
+        let input = MemoryInput::new();
+        let feeder = input.clone();
+        let feeding = thread::spawn(move || {
+            feeder.push(MidiEvent::new(MidiEventKind::NoteOn { key: 69, velocity: 100 }, 0));
+            thread::sleep(Duration::from_millis(20));
+            feeder.push(MidiEvent::new(MidiEventKind::NoteOff { key: 69, velocity: 0 }, 0));
+            thread::sleep(Duration::from_millis(20));
+            feeder.close();
+        });
+
+        let output = AudioOut::with_sink(Box::new(WavSink::new(&path, &config).unwrap()), &config);
+        let mode = MidiInputPlayer::with_output(Box::new(input), Synth::new(&config), output);
+        Player::new(Box::new(mode)).play().unwrap();
+        feeding.join().unwrap();
+
+        let (header, data) = wav::read(&mut File::open(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        let BitDepth::Eight(samples) = data else { panic!("expected 8 bit samples") };
+        assert_eq!(header.sampling_rate, config.sample_rate);
+        // the note sounded, then stopped well before the input closed
+        assert!(samples.iter().any(|&sample| sample != silence));
+        assert!(samples[samples.len() - config.buffer_size..].iter().all(|&sample| sample == silence));
+    }
+}
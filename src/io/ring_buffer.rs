@@ -0,0 +1,224 @@
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// storage shared by the two ends of a ring buffer
+///
+/// `head` and `tail` count reads and writes modulo twice the capacity, so a full
+/// buffer can be told from an empty one, the slot they point to being their value
+/// modulo the capacity
+struct Shared<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    head: AtomicUsize,      // next slot to read, only written by the consumer
+    tail: AtomicUsize,      // next slot to write, only written by the producer
+}
+
+// a slot is only ever accessed by one end at a time, handed over through `head` and `tail`
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+impl<T> Shared<T> {
+    fn slot(&self, index: usize) -> *mut MaybeUninit<T> {
+        self.slots[index % self.slots.len()].get()
+    }
+
+    /// count following `index`
+    fn next(&self, index: usize) -> usize {
+        (index + 1) % (2 * self.slots.len())
+    }
+
+    /// values waiting between `head` and `tail`
+    fn len(&self, head: usize, tail: usize) -> usize {
+        (tail + 2 * self.slots.len() - head) % (2 * self.slots.len())
+    }
+}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        let tail = *self.tail.get_mut();
+        let mut head = *self.head.get_mut();
+        while head != tail {
+            unsafe { (*self.slot(head)).assume_init_drop() };
+            head = self.next(head);
+        }
+    }
+}
+
+/// writing end of a ring buffer
+pub struct Producer<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// reading end of a ring buffer
+pub struct Consumer<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// lock free single producer, single consumer queue holding up to `capacity` values
+///
+/// neither end ever blocks, so the consumer can live on the audio thread
+pub fn ring_buffer<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    let slots = (0..capacity.max(1))
+        .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+        .collect();
+    let shared = Arc::new(Shared {
+        slots,
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+    });
+    (Producer { shared: shared.clone() }, Consumer { shared })
+}
+
+impl<T> Producer<T> {
+
+    /// appends `value`, handing it back if the buffer is full
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        let tail = self.shared.tail.load(Ordering::Relaxed);
+        let head = self.shared.head.load(Ordering::Acquire);
+        if self.shared.len(head, tail) == self.shared.slots.len() {
+            return Err(value);
+        }
+        unsafe { (*self.shared.slot(tail)).write(value) };
+        self.shared.tail.store(self.shared.next(tail), Ordering::Release);
+        Ok(())
+    }
+
+    pub fn is_full(&self) -> bool {
+        let tail = self.shared.tail.load(Ordering::Relaxed);
+        let head = self.shared.head.load(Ordering::Acquire);
+        self.shared.len(head, tail) == self.shared.slots.len()
+    }
+}
+
+impl<T> Consumer<T> {
+
+    /// oldest value, without removing it
+    pub fn peek(&self) -> Option<&T> {
+        let head = self.shared.head.load(Ordering::Relaxed);
+        let tail = self.shared.tail.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+        Some(unsafe { (*self.shared.slot(head)).assume_init_ref() })
+    }
+
+    /// removes and returns the oldest value
+    pub fn pop(&mut self) -> Option<T> {
+        let head = self.shared.head.load(Ordering::Relaxed);
+        let tail = self.shared.tail.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+        let value = unsafe { (*self.shared.slot(head)).assume_init_read() };
+        self.shared.head.store(self.shared.next(head), Ordering::Release);
+        Some(value)
+    }
+
+    /// removes and returns the oldest value if it satisfies `f`
+    pub fn pop_if(&mut self, f: impl FnOnce(&T) -> bool) -> Option<T> {
+        if f(self.peek()?) {
+            self.pop()
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn empty_buffer_has_nothing_to_pop() {
+        let (producer, mut consumer) = ring_buffer::<u32>(4);
+        assert!(!producer.is_full());
+        assert_eq!(consumer.peek(), None);
+        assert_eq!(consumer.pop(), None);
+        assert_eq!(consumer.pop_if(|_| true), None);
+    }
+
+    #[test]
+    fn full_buffer_hands_values_back() {
+        let (mut producer, mut consumer) = ring_buffer(3);
+        for value in 0..3 {
+            assert_eq!(producer.push(value), Ok(()));
+        }
+        assert!(producer.is_full());
+        assert_eq!(producer.push(3), Err(3));
+
+        assert_eq!(consumer.pop(), Some(0));
+        assert!(!producer.is_full());
+        assert_eq!(producer.push(3), Ok(()));
+        assert_eq!((1..4).map(|_| consumer.pop()).collect::<Vec<_>>(), [Some(1), Some(2), Some(3)]);
+        assert_eq!(consumer.pop(), None);
+    }
+
+    #[test]
+    fn values_keep_their_order_around_the_end() {
+        // a capacity that isn't a power of two, so the counts wrap unevenly
+        let (mut producer, mut consumer) = ring_buffer(3);
+        for round in 0..100 {
+            let n = round % 3 + 1;
+            for value in 0..n {
+                assert_eq!(producer.push(round * 10 + value), Ok(()));
+            }
+            for value in 0..n {
+                assert_eq!(consumer.pop(), Some(round * 10 + value));
+            }
+            assert_eq!(consumer.pop(), None);
+        }
+    }
+
+    #[test]
+    fn pop_if_leaves_values_that_fail() {
+        let (mut producer, mut consumer) = ring_buffer(4);
+        producer.push(1).unwrap();
+        producer.push(5).unwrap();
+        assert_eq!(consumer.pop_if(|&value| value > 2), None);
+        assert_eq!(consumer.peek(), Some(&1));
+        assert_eq!(consumer.pop_if(|&value| value < 2), Some(1));
+        assert_eq!(consumer.pop_if(|&value| value < 2), None);
+        assert_eq!(consumer.pop_if(|&value| value > 2), Some(5));
+    }
+
+    #[test]
+    fn values_left_are_dropped_with_the_buffer() {
+        let value = Arc::new(());
+        let (mut producer, consumer) = ring_buffer(4);
+        for _ in 0..3 {
+            producer.push(value.clone()).unwrap();
+        }
+        drop((producer, consumer));
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn threads_pass_every_value_in_order() {
+        const VALUES: u64 = 200_000;
+        let (mut producer, mut consumer) = ring_buffer(64);
+
+        let writer = thread::spawn(move || {
+            for value in 0..VALUES {
+                let mut value = value;
+                while let Err(full) = producer.push(value) {
+                    value = full;
+                    thread::yield_now();
+                }
+            }
+        });
+
+        let mut expected = 0;
+        while expected < VALUES {
+            match consumer.pop() {
+                Some(value) => {
+                    assert_eq!(value, expected);
+                    expected += 1;
+                }
+                None => thread::yield_now(),
+            }
+        }
+        writer.join().unwrap();
+        assert_eq!(consumer.pop(), None);
+    }
+}
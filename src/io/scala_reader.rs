@@ -0,0 +1,214 @@
+use std::fs;
+use std::path::Path;
+
+use crate::synth::tuning::{ratio2cents, KeyboardMapping, Scale};
+use crate::{Error, Result};
+
+/// lines of a Scala file that are not comments
+fn content_lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim_end_matches('\r')))
+        .filter(|(_, line)| !line.starts_with('!'))
+}
+
+/// parses one pitch of a .scl file, in cents if it has a dot, otherwise as a ratio
+fn parse_pitch(token: &str) -> Option<f64> {
+    if token.contains('.') {
+        return token.parse::<f64>().ok();
+    }
+    let (num, den) = match token.split_once('/') {
+        Some((num, den)) => (num.parse::<f64>().ok()?, den.parse::<f64>().ok()?),
+        None => (token.parse::<f64>().ok()?, 1.),
+    };
+    if num <= 0. || den <= 0. {
+        return None;
+    }
+    Some(ratio2cents(num / den))
+}
+
+/// parses the contents of a Scala scale (.scl) file
+pub fn parse_scl(text: &str) -> Result<Scale> {
+    let mut lines = content_lines(text);
+
+    let (_, description) = lines.next().ok_or(Error::ScalaParse("missing scale description".to_string()))?;
+
+    let (line_n, count) = lines.next().ok_or(Error::ScalaParse("missing number of notes".to_string()))?;
+    let count = count.trim().parse::<usize>()
+        .map_err(|_| Error::ScalaParse(format!("line {line_n}: invalid number of notes")))?;
+
+    let mut cents = Vec::with_capacity(count);
+    for (line_n, line) in lines.take(count) {
+        let token = line.split_whitespace().next()
+            .ok_or_else(|| Error::ScalaParse(format!("line {line_n}: missing pitch")))?;
+        let pitch = parse_pitch(token)
+            .ok_or_else(|| Error::ScalaParse(format!("line {line_n}: invalid pitch '{token}'")))?;
+        cents.push(pitch);
+    }
+
+    if cents.len() < count {
+        return Err(Error::ScalaParse(format!("expected {count} notes, found {}", cents.len())));
+    }
+
+    Ok(Scale::new(description.trim(), cents))
+}
+
+/// first token of the next non empty line, along with its line number
+fn next_token<'a>(lines: &mut impl Iterator<Item = (usize, &'a str)>, name: &str) -> Result<(usize, &'a str)> {
+    lines.find(|(_, line)| !line.trim().is_empty())
+        .and_then(|(line_n, line)| line.split_whitespace().next().map(|token| (line_n, token)))
+        .ok_or_else(|| Error::ScalaParse(format!("missing {name}")))
+}
+
+fn next_number<'a>(lines: &mut impl Iterator<Item = (usize, &'a str)>, name: &str) -> Result<i32> {
+    let (line_n, token) = next_token(lines, name)?;
+    token.parse::<i32>().map_err(|_| Error::ScalaParse(format!("line {line_n}: invalid {name} '{token}'")))
+}
+
+fn next_key<'a>(lines: &mut impl Iterator<Item = (usize, &'a str)>, name: &str) -> Result<u8> {
+    let value = next_number(lines, name)?;
+    u8::try_from(value).ok().filter(|&key| key < 128)
+        .ok_or_else(|| Error::ScalaParse(format!("{name} {value} is not a midi key")))
+}
+
+/// parses the contents of a Scala keyboard mapping (.kbm) file
+pub fn parse_kbm(text: &str) -> Result<KeyboardMapping> {
+    let mut lines = content_lines(text);
+
+    let size = next_number(&mut lines, "map size")?;
+    let first_key = next_key(&mut lines, "first key")?;
+    let last_key = next_key(&mut lines, "last key")?;
+    let middle_key = next_key(&mut lines, "middle key")?;
+    let reference_key = next_key(&mut lines, "reference key")?;
+
+    let (line_n, token) = next_token(&mut lines, "reference frequency")?;
+    let reference_freq = token.parse::<f32>().ok().filter(|&freq| freq > 0.)
+        .ok_or_else(|| Error::ScalaParse(format!("line {line_n}: invalid reference frequency '{token}'")))?;
+
+    let octave_degree = next_number(&mut lines, "octave degree")?;
+
+    let mut map = vec![];
+    for _ in 0..size {
+        let (line_n, token) = next_token(&mut lines, "mapping entry")?;
+        if token == "x" {
+            map.push(None);
+        } else {
+            let degree = token.parse::<i32>()
+                .map_err(|_| Error::ScalaParse(format!("line {line_n}: invalid mapping entry '{token}'")))?;
+            map.push(Some(degree));
+        }
+    }
+
+    Ok(KeyboardMapping::new(first_key, last_key, middle_key, reference_key, reference_freq, octave_degree, map))
+}
+
+/// reads a Scala scale (.scl) file
+pub fn read_scl(path: impl AsRef<Path>) -> Result<Scale> {
+    let text = fs::read_to_string(path)?;
+    parse_scl(&text)
+}
+
+/// reads a Scala keyboard mapping (.kbm) file
+pub fn read_kbm(path: impl AsRef<Path>) -> Result<KeyboardMapping> {
+    let text = fs::read_to_string(path)?;
+    parse_kbm(&text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MEANTONE: &str = "\
+! meantone.scl
+!
+ Quarter-comma meantone, partial
+ 4
+!
+ 193.157
+ 5/4
+ 3/2 a fifth
+ 2
+";
+
+    fn assert_cents(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-3, "{actual} cents, expected {expected}");
+    }
+
+    #[test]
+    fn scl_pitches_are_cents_or_ratios() {
+        let scale = parse_scl(MEANTONE).unwrap();
+        assert_eq!(scale.description(), "Quarter-comma meantone, partial");
+        assert_eq!(scale.len(), 4);
+        assert_cents(scale.degree2cents(0), 0.);
+        assert_cents(scale.degree2cents(1), 193.157);
+        assert_cents(scale.degree2cents(2), 386.314);
+        assert_cents(scale.degree2cents(3), 701.955);
+        assert_cents(scale.degree2cents(4), 1200.);
+        assert_cents(scale.degree2cents(5), 1393.157);
+        assert_cents(scale.degree2cents(-1), -498.045);
+    }
+
+    #[test]
+    fn scl_description_may_be_empty() {
+        let scale = parse_scl("!\n\n1\n1200.0\n").unwrap();
+        assert_eq!(scale.description(), "");
+        assert_eq!(scale.len(), 1);
+    }
+
+    #[test]
+    fn malformed_scl_is_rejected() {
+        assert!(parse_scl("").is_err());
+        assert!(parse_scl("short\n3\n100.0\n200.0\n").is_err());
+        assert!(parse_scl("bad count\nthree\n").is_err());
+        assert!(parse_scl("bad pitch\n1\nfifth\n").is_err());
+        assert!(parse_scl("negative ratio\n1\n-3/2\n").is_err());
+    }
+
+    #[test]
+    fn kbm_reads_every_field_and_unmapped_keys() {
+        let kbm = "\
+! white keys only
+7
+0
+127
+60
+69
+440.0
+12
+! mapping
+0
+x
+2
+x
+4
+5
+x
+";
+        let mapping = parse_kbm(kbm).unwrap();
+        assert_eq!(mapping.reference_freq(), 440.);
+        assert_eq!(mapping.key2degree(60, 12), Some(0));
+        assert_eq!(mapping.key2degree(61, 12), None);
+        assert_eq!(mapping.key2degree(62, 12), Some(2));
+        assert_eq!(mapping.key2degree(65, 12), Some(5));
+        assert_eq!(mapping.key2degree(67, 12), Some(12));
+        assert_eq!(mapping.key2degree(53, 12), Some(-12));
+        assert_eq!(mapping.key2degree(58, 12), Some(-12 + 5));
+    }
+
+    #[test]
+    fn kbm_octave_degree_0_repeats_at_the_scale_size() {
+        let mapping = parse_kbm("2\n0\n127\n60\n60\n261.6\n0\n0\n1\n").unwrap();
+        assert_eq!(mapping.key2degree(62, 5), Some(5));
+        assert_eq!(mapping.key2degree(63, 5), Some(6));
+        assert_eq!(mapping.key2degree(59, 5), Some(-4));
+    }
+
+    #[test]
+    fn malformed_kbm_is_rejected() {
+        assert!(parse_kbm("0\n0\n127\n60\n69\n").is_err());
+        assert!(parse_kbm("0\n0\n128\n60\n69\n440\n12\n").is_err());
+        assert!(parse_kbm("0\n0\n127\n60\n69\n-440\n12\n").is_err());
+        assert!(parse_kbm("2\n0\n127\n60\n69\n440\n12\n0\n").is_err());
+        assert!(parse_kbm("1\n0\n127\n60\n69\n440\n12\ny\n").is_err());
+    }
+}
@@ -0,0 +1,588 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::EngineConfig;
+use crate::{Error, Result};
+
+/// duty cycle of notes in a text score, until a `duty` directive changes it
+pub const SCORE_DUTY: f32 = 0.1;
+
+/// velocity of notes in a text score, until a `velocity` directive changes it
+pub const SCORE_VELOCITY: u8 = 127;
+
+/// beats per minute of a text score, until a `tempo` directive changes it
+pub const SCORE_TEMPO: f64 = 120.;
+
+/// how deep `include` directives may nest
+const INCLUDE_DEPTH_MAX: usize = 16;
+
+/// pitch of a score note
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pitch {
+    Key(u8),        // midi key, played through the channel's tuning
+    Freq(f32),      // frequency in Hz
+}
+
+/// a note starting or stopping in a text score
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScoreEvent {
+    pub us: u64,            // absolute time in microseconds
+    pub channel: usize,
+    pub on: bool,           // whether the note starts or stops
+    pub pitch: Pitch,
+    pub velocity: u8,
+    pub duty: f32,
+}
+
+/// the events of one score file, in time order
+#[derive(Clone, Debug, Default)]
+pub struct ScoreTrack {
+    events: Vec<ScoreEvent>,
+    tempos: Vec<(u64, f64)>,    // tempo changes in beats per minute and when they happen in microseconds
+    cursor: usize,
+}
+
+impl ScoreTrack {
+
+    pub fn new(mut events: Vec<ScoreEvent>) -> Self {
+        // stable, so a note stopping and one starting at the same time keep their order
+        events.sort_by_key(|event| event.us);
+        Self {
+            events,
+            tempos: vec![],
+            cursor: 0,
+        }
+    }
+
+    /// sets the tempo changes of the track, in beats per minute along with when
+    /// they happen in microseconds
+    pub fn with_tempos(mut self, mut tempos: Vec<(u64, f64)>) -> Self {
+        tempos.sort_by_key(|&(us, _)| us);
+        self.tempos = tempos;
+        self
+    }
+
+    /// reads a score file, its notes going to `channel` unless a `channel` directive
+    /// says otherwise
+    ///
+    /// a score is made of lines of
+    /// - `status freq delay_us`, the original format: waits `delay_us` microseconds,
+    ///   then starts (`status` other than 0) or stops a note at `freq` Hz
+    /// - `C#4 1/2 [duty] [v100]`: plays a note for a number of beats, optionally with
+    ///   its own duty cycle and velocity
+    /// - `r 1` or `rest 1`: waits a number of beats
+    /// - `tempo 140`, `duty 0.25`, `velocity 100`, `channel 2`: change the beats per
+    ///   minute, the duty cycle, the velocity and the channel of the following notes,
+    ///   each channel keeping its own time
+    /// - `repeat 4` ... `end`: plays the lines in between 4 times
+    /// - `include other.txt`: reads another score in place, relative to this one
+    ///
+    /// `;` and a `#` starting a word begin a comment
+    pub fn open(path: impl AsRef<Path>, channel: usize, config: &EngineConfig) -> Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        Self::from_lines(expand(&text, &path.display().to_string(), &dir, 0)?, channel, config)
+    }
+
+    /// parses score text, as `open` does, including files relative to the working directory
+    pub fn parse(text: &str, channel: usize, config: &EngineConfig) -> Result<Self> {
+        Self::from_lines(expand(text, "score", Path::new(""), 0)?, channel, config)
+    }
+
+    fn from_lines(lines: Vec<Line>, channel: usize, config: &EngineConfig) -> Result<Self> {
+        let mut parser = Parser::new(channel, config)?;
+        parser.run(&lines)?;
+        Ok(Self::new(parser.events).with_tempos(parser.tempos))
+    }
+
+    pub fn events(&self) -> &[ScoreEvent] {
+        &self.events
+    }
+
+    pub fn tempos(&self) -> &[(u64, f64)] {
+        &self.tempos
+    }
+}
+
+/// a set of score tracks played together, track `i` starting on channel `i`
+#[derive(Clone, Debug, Default)]
+pub struct Score {
+    tracks: Vec<ScoreTrack>,
+}
+
+impl Score {
+
+    pub fn new(tracks: Vec<ScoreTrack>) -> Self {
+        Self {
+            tracks,
+        }
+    }
+
+    /// reads one score file per channel, in order, for a synth with `config`
+    pub fn open<P: AsRef<Path>>(paths: &[P], config: &EngineConfig) -> Result<Self> {
+        let tracks = paths.iter()
+            .enumerate()
+            .map(|(channel, path)| ScoreTrack::open(path, channel, config))
+            .collect::<Result<_>>()?;
+        Ok(Self::new(tracks))
+    }
+
+    pub fn tracks(&self) -> &[ScoreTrack] {
+        &self.tracks
+    }
+
+    /// tempo changes of every track in time order
+    pub fn tempos(&self) -> Vec<(u64, f64)> {
+        let mut tempos: Vec<_> = self.tracks.iter().flat_map(|track| track.tempos.iter().copied()).collect();
+        tempos.sort_by_key(|&(us, _)| us);
+        tempos
+    }
+
+    /// returns the next event across all tracks in time order
+    ///
+    /// tracks end independently, returns `None` once every track is exhausted
+    pub fn next_event(&mut self) -> Option<ScoreEvent> {
+        let (track_n, _) = self.tracks.iter()
+            .enumerate()
+            .filter_map(|(i, track)| track.events.get(track.cursor).map(|event| (i, event.us)))
+            .min_by_key(|&(i, us)| (us, i))?;
+        let track = &mut self.tracks[track_n];
+        track.cursor += 1;
+        Some(track.events[track.cursor - 1])
+    }
+
+    /// rewinds every track to its first event
+    pub fn rewind(&mut self) {
+        for track in self.tracks.iter_mut() {
+            track.cursor = 0;
+        }
+    }
+}
+
+/// a line of score text, after includes are expanded and comments removed
+struct Line {
+    origin: String,                 // file the line comes from
+    number: usize,
+    tokens: Vec<(usize, String)>,   // words and the column they start at
+}
+
+impl Line {
+    fn error(&self, column: usize, message: &str) -> Error {
+        Error::ScoreParse(format!("{}:{}:{column}: {message}", self.origin, self.number))
+    }
+
+    /// error pointing at token `i`, or past the end of the line if it is missing
+    fn token_error(&self, i: usize, message: &str) -> Error {
+        let column = match self.tokens.get(i) {
+            Some((column, _)) => *column,
+            None => self.tokens.last().map_or(1, |(column, token)| column + token.len() + 1),
+        };
+        self.error(column, message)
+    }
+
+    fn token(&self, i: usize, name: &str) -> Result<&str> {
+        self.tokens.get(i)
+            .map(|(_, token)| token.as_str())
+            .ok_or_else(|| self.token_error(i, &format!("missing {name}")))
+    }
+
+    fn parse<T: std::str::FromStr>(&self, i: usize, name: &str) -> Result<T> {
+        let token = self.token(i, name)?;
+        token.parse().map_err(|_| self.token_error(i, &format!("invalid {name} '{token}'")))
+    }
+
+    fn expect_end(&self, len: usize) -> Result<()> {
+        match self.tokens.get(len) {
+            Some((column, token)) => Err(self.error(*column, &format!("unexpected '{token}'"))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// splits a line into words and their columns, up to a comment
+fn tokenize(text: &str) -> Vec<(usize, String)> {
+    let text = text.split(';').next().unwrap_or("");
+    let mut tokens = vec![];
+    let mut start = None;
+    for (i, ch) in text.char_indices().chain([(text.len(), ' ')]) {
+        match (ch.is_whitespace(), start) {
+            (false, None) => start = Some(i),
+            (true, Some(s)) => {
+                tokens.push((s + 1, text[s..i].to_string()));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    let comment = tokens.iter().position(|(_, token)| token.starts_with('#'));
+    tokens.truncate(comment.unwrap_or(tokens.len()));
+    tokens
+}
+
+/// splits `text` into lines, replacing `include` directives by the lines of the file
+/// they name, relative to `dir`
+fn expand(text: &str, origin: &str, dir: &Path, depth: usize) -> Result<Vec<Line>> {
+    let mut lines = vec![];
+    for (i, text) in text.lines().enumerate() {
+        let line = Line {
+            origin: origin.to_string(),
+            number: i + 1,
+            tokens: tokenize(text),
+        };
+        if line.tokens.is_empty() {
+            continue;
+        }
+        if line.tokens[0].1 != "include" {
+            lines.push(line);
+            continue;
+        }
+
+        let name = line.token(1, "file name")?;
+        line.expect_end(2)?;
+        if depth >= INCLUDE_DEPTH_MAX {
+            return Err(line.error(line.tokens[0].0, "includes nested too deep"));
+        }
+        let path: PathBuf = dir.join(name);
+        let text = fs::read_to_string(&path)
+            .map_err(|err| line.token_error(1, &format!("can't read '{}': {err}", path.display())))?;
+        let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        lines.extend(expand(&text, &path.display().to_string(), &dir, depth + 1)?);
+    }
+    Ok(lines)
+}
+
+/// parses a note name like `C4`, `f#3` or `Bb-1` into a midi key
+fn name2key(name: &str) -> Option<u8> {
+    let mut chars = name.chars();
+    let semitone = match chars.next()?.to_ascii_uppercase() {
+        'C' => 0, 'D' => 2, 'E' => 4, 'F' => 5, 'G' => 7, 'A' => 9, 'B' => 11,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    let accidentals = rest.len() - rest.trim_start_matches(['#', 'b']).len();
+    let (accidentals, octave) = rest.split_at(accidentals);
+    let shift = accidentals.chars().map(|ch| if ch == '#' { 1 } else { -1 }).sum::<i32>();
+    let octave = octave.parse::<i32>().ok()?;
+    let key = (octave + 1) * 12 + semitone + shift;
+    u8::try_from(key).ok().filter(|&key| key < 128)
+}
+
+/// parses a duration in beats, either a decimal number or a fraction like `3/4`
+fn token2beats(token: &str) -> Option<f64> {
+    let beats = match token.split_once('/') {
+        Some((num, den)) => num.parse::<f64>().ok()? / den.parse::<f64>().ok()?,
+        None => token.parse::<f64>().ok()?,
+    };
+    Some(beats).filter(|beats| beats.is_finite() && *beats >= 0.)
+}
+
+/// the state of a score as its lines are played
+struct Parser {
+    tempo: f64,             // beats per minute
+    duty: f32,
+    velocity: u8,
+    channel: usize,
+    channels_max: usize,    // channels the synth playing the score has
+    cursors: Vec<f64>,      // current time of each channel in microseconds
+    events: Vec<ScoreEvent>,
+    tempos: Vec<(u64, f64)>,
+}
+
+impl Parser {
+
+    fn new(channel: usize, config: &EngineConfig) -> Result<Self> {
+        if channel >= config.channels_max {
+            return Err(Error::ScoreParse(format!("channel {channel} is past the last channel")));
+        }
+        Ok(Self {
+            tempo: SCORE_TEMPO,
+            duty: SCORE_DUTY,
+            velocity: SCORE_VELOCITY,
+            channel,
+            channels_max: config.channels_max,
+            cursors: vec![0.; config.channels_max],
+            events: vec![],
+            tempos: vec![],
+        })
+    }
+
+    fn beats2us(&self, beats: f64) -> f64 {
+        beats * 60_000_000. / self.tempo
+    }
+
+    fn push(&mut self, on: bool, pitch: Pitch, velocity: u8, duty: f32, us: f64) {
+        self.events.push(ScoreEvent {
+            us: us.round() as u64,
+            channel: self.channel,
+            on,
+            pitch,
+            velocity,
+            duty,
+        });
+    }
+
+    /// plays `lines`, expanding repeat blocks
+    fn run(&mut self, lines: &[Line]) -> Result<()> {
+        let mut i = 0;
+        while i < lines.len() {
+            let line = &lines[i];
+            match line.tokens[0].1.as_str() {
+                "repeat" => {
+                    let count = line.parse::<usize>(1, "repeat count")?;
+                    line.expect_end(2)?;
+                    let end = Self::block_end(lines, i)?;
+                    lines[end].expect_end(1)?;
+                    for _ in 0..count {
+                        self.run(&lines[i + 1..end])?;
+                    }
+                    i = end;
+                }
+                "end" => return Err(line.error(line.tokens[0].0, "'end' without 'repeat'")),
+                _ => self.line(line)?,
+            }
+            i += 1;
+        }
+        Ok(())
+    }
+
+    /// index of the `end` closing the `repeat` at `start`
+    fn block_end(lines: &[Line], start: usize) -> Result<usize> {
+        let mut depth = 0;
+        for (i, line) in lines.iter().enumerate().skip(start) {
+            match line.tokens[0].1.as_str() {
+                "repeat" => depth += 1,
+                "end" => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                return Ok(i);
+            }
+        }
+        let line = &lines[start];
+        Err(line.error(line.tokens[0].0, "'repeat' without 'end'"))
+    }
+
+    fn line(&mut self, line: &Line) -> Result<()> {
+        let keyword = line.tokens[0].1.as_str();
+        match keyword {
+            "tempo" => {
+                let tempo = line.parse::<f64>(1, "tempo")?;
+                if !(tempo > 0. && tempo.is_finite()) {
+                    return Err(line.token_error(1, "tempo must be positive"));
+                }
+                self.tempo = tempo;
+                self.tempos.push((self.cursors[self.channel].round() as u64, tempo));
+                line.expect_end(2)
+            }
+            "duty" => {
+                self.duty = Self::parse_duty(line, 1)?;
+                line.expect_end(2)
+            }
+            "velocity" => {
+                self.velocity = Self::parse_velocity(line, 1, line.token(1, "velocity")?)?;
+                line.expect_end(2)
+            }
+            "channel" => {
+                let channel = line.parse::<usize>(1, "channel")?;
+                if channel >= self.channels_max {
+                    return Err(line.token_error(1, &format!("channel must be below {}", self.channels_max)));
+                }
+                self.channel = channel;
+                line.expect_end(2)
+            }
+            "r" | "rest" => {
+                let beats = Self::parse_beats(line, 1)?;
+                self.cursors[self.channel] += self.beats2us(beats);
+                line.expect_end(2)
+            }
+            _ if keyword.starts_with(|ch: char| ch.is_ascii_digit()) => self.legacy(line),
+            _ => self.note(line),
+        }
+    }
+
+    /// `status freq delay_us`
+    fn legacy(&mut self, line: &Line) -> Result<()> {
+        let status = line.parse::<u32>(0, "status")?;
+        let freq = line.parse::<u32>(1, "frequency")?;
+        let delay_us = line.parse::<u64>(2, "delay")?;
+        line.expect_end(3)?;
+        self.cursors[self.channel] += delay_us as f64;
+        self.push(status != 0, Pitch::Freq(freq as f32), self.velocity, self.duty, self.cursors[self.channel]);
+        Ok(())
+    }
+
+    /// `name beats [duty] [vN]`
+    fn note(&mut self, line: &Line) -> Result<()> {
+        let name = line.token(0, "note")?;
+        let key = name2key(name).ok_or_else(|| line.token_error(0, &format!("unknown note or directive '{name}'")))?;
+        let beats = Self::parse_beats(line, 1)?;
+
+        let mut duty = self.duty;
+        let mut velocity = self.velocity;
+        let mut i = 2;
+        if i < line.tokens.len() && !line.tokens[i].1.starts_with('v') {
+            duty = Self::parse_duty(line, i)?;
+            i += 1;
+        }
+        if let Some(token) = line.tokens.get(i).and_then(|(_, token)| token.strip_prefix('v')) {
+            velocity = Self::parse_velocity(line, i, token)?;
+            i += 1;
+        }
+        line.expect_end(i)?;
+
+        let start = self.cursors[self.channel];
+        let stop = start + self.beats2us(beats);
+        self.push(true, Pitch::Key(key), velocity, duty, start);
+        self.push(false, Pitch::Key(key), velocity, duty, stop);
+        self.cursors[self.channel] = stop;
+        Ok(())
+    }
+
+    fn parse_beats(line: &Line, i: usize) -> Result<f64> {
+        let token = line.token(i, "duration")?;
+        token2beats(token).ok_or_else(|| line.token_error(i, &format!("invalid duration '{token}'")))
+    }
+
+    /// `token`, the text of token `i` without any `v` prefix, as a velocity
+    fn parse_velocity(line: &Line, i: usize, token: &str) -> Result<u8> {
+        match token.parse::<u8>() {
+            Ok(velocity) if velocity <= 127 => Ok(velocity),
+            _ => Err(line.token_error(i, "velocity must be a whole number from 0 to 127")),
+        }
+    }
+
+    fn parse_duty(line: &Line, i: usize) -> Result<f32> {
+        let duty = line.parse::<f32>(i, "duty")?;
+        if !(duty > 0. && duty <= 1.) {
+            return Err(line.token_error(i, "duty must be in (0, 1]"));
+        }
+        Ok(duty)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<ScoreTrack> {
+        ScoreTrack::parse(text, 0, &EngineConfig::default())
+    }
+
+    /// time, channel, whether it starts and pitch of every event
+    fn events(text: &str) -> Vec<(u64, usize, bool, Pitch)> {
+        let track = parse(text).unwrap();
+        track.events().iter().map(|event| (event.us, event.channel, event.on, event.pitch)).collect()
+    }
+
+    fn error(text: &str) -> String {
+        match parse(text) {
+            Err(Error::ScoreParse(message)) => message,
+            other => panic!("expected a parse error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn note_names_are_midi_keys() {
+        for (name, key) in [("C4", 60), ("a4", 69), ("F#3", 54), ("Bb-1", 10), ("Cb4", 59), ("E##4", 66), ("G9", 127)] {
+            assert_eq!(name2key(name), Some(key), "{name}");
+        }
+        for name in ["H4", "C", "G#9", "C-2", "4C"] {
+            assert_eq!(name2key(name), None, "{name}");
+        }
+    }
+
+    #[test]
+    fn notes_last_their_beats_at_the_tempo() {
+        let key = |key| Pitch::Key(key);
+        assert_eq!(events("C4 1\nD4 1/2\nr 1\nE4 0.25"), [
+            (0, 0, true, key(60)),
+            (500_000, 0, false, key(60)),
+            (500_000, 0, true, key(62)),
+            (750_000, 0, false, key(62)),
+            (1_250_000, 0, true, key(64)),
+            (1_375_000, 0, false, key(64)),
+        ]);
+    }
+
+    #[test]
+    fn tempo_changes_the_following_notes() {
+        let track = parse("C4 1\ntempo 60\nC4 1").unwrap();
+        let times: Vec<u64> = track.events().iter().map(|event| event.us).collect();
+        assert_eq!(times, [0, 500_000, 500_000, 1_500_000]);
+        assert_eq!(track.tempos(), [(500_000, 60.)]);
+    }
+
+    #[test]
+    fn duty_applies_until_a_note_overrides_it() {
+        let track = parse("C4 1\nduty 0.25\nC4 1\nC4 1 0.5\nC4 1").unwrap();
+        let duties: Vec<f32> = track.events().iter().filter(|event| event.on).map(|event| event.duty).collect();
+        assert_eq!(duties, [SCORE_DUTY, 0.25, 0.5, 0.25]);
+    }
+
+    #[test]
+    fn channels_keep_their_own_time() {
+        let key = |key| Pitch::Key(key);
+        assert_eq!(events("C4 1\nchannel 2\nE4 1\nchannel 0\nG4 1"), [
+            (0, 0, true, key(60)),
+            (0, 2, true, key(64)),
+            (500_000, 0, false, key(60)),
+            (500_000, 2, false, key(64)),
+            (500_000, 0, true, key(67)),
+            (1_000_000, 0, false, key(67)),
+        ]);
+    }
+
+    #[test]
+    fn repeats_nest() {
+        let track = parse("repeat 2\nC4 1\nrepeat 3 ; inner\nD4 1\nend\nend\nE4 1").unwrap();
+        let keys: Vec<Pitch> = track.events().iter().filter(|event| event.on).map(|event| event.pitch).collect();
+        let expected: Vec<Pitch> = [60, 62, 62, 62, 60, 62, 62, 62, 64].into_iter().map(Pitch::Key).collect();
+        assert_eq!(keys, expected);
+    }
+
+    #[test]
+    fn legacy_lines_are_frequencies_and_delays() {
+        assert_eq!(events("1 440 0\n0 440 1000\n# comment\n\n1 220 500"), [
+            (0, 0, true, Pitch::Freq(440.)),
+            (1000, 0, false, Pitch::Freq(440.)),
+            (1500, 0, true, Pitch::Freq(220.)),
+        ]);
+    }
+
+    #[test]
+    fn includes_read_relative_to_the_including_file() {
+        let dir = std::env::temp_dir().join(format!("obs_score_include_{}", std::process::id()));
+        fs::create_dir_all(dir.join("parts")).unwrap();
+        fs::write(dir.join("main.txt"), "C4 1\ninclude parts/verse.txt\nC4 1\n").unwrap();
+        fs::write(dir.join("parts/verse.txt"), "include chorus.txt\nD4 1\n").unwrap();
+        fs::write(dir.join("parts/chorus.txt"), "E4 1\n").unwrap();
+
+        let track = ScoreTrack::open(dir.join("main.txt"), 0, &EngineConfig::default());
+        fs::remove_dir_all(&dir).unwrap();
+        let keys: Vec<Pitch> = track.unwrap().events().iter().filter(|event| event.on).map(|event| event.pitch).collect();
+        assert_eq!(keys, [60, 64, 62, 60].map(Pitch::Key));
+    }
+
+    #[test]
+    fn errors_point_at_the_line_and_column() {
+        assert_eq!(error("C4 1\n  X4 1"), "score:2:3: unknown note or directive 'X4'");
+        assert_eq!(error("C4"), "score:1:4: missing duration");
+        assert_eq!(error("C4 1/0"), "score:1:4: invalid duration '1/0'");
+        assert_eq!(error("C4 1 2"), "score:1:6: duty must be in (0, 1]");
+        assert_eq!(error("tempo 0"), "score:1:7: tempo must be positive");
+        assert_eq!(error("duty 0.5 0.5"), "score:1:10: unexpected '0.5'");
+        assert_eq!(error("channel 16"), "score:1:9: channel must be below 16");
+        assert_eq!(error("1 440"), "score:1:7: missing delay");
+        assert_eq!(error("include missing_file.txt").split(": can't").next(), Some("score:1:9"));
+    }
+
+    #[test]
+    fn repeat_blocks_must_be_closed() {
+        assert_eq!(error("C4 1\nrepeat 2\nC4 1"), "score:2:1: 'repeat' without 'end'");
+        assert_eq!(error("C4 1\nend"), "score:2:1: 'end' without 'repeat'");
+        assert_eq!(error("repeat 2\nC4 1\nend now"), "score:3:5: unexpected 'now'");
+        assert_eq!(error("repeat two\nend"), "score:1:8: invalid repeat count 'two'");
+    }
+}
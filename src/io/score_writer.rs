@@ -0,0 +1,106 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::io::midi_reader::{MidiEventKind, MidiFile};
+use crate::synth::tuning::{midi2freq, A4_FREQ};
+use crate::Result;
+
+/// a `status freq delay_us` line of a text score
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScoreLine {
+    pub on: bool,
+    pub freq: u32,          // frequency in Hz
+    pub delay_us: u64,      // time since the previous line in microseconds
+}
+
+/// one monophonic line split out of a midi file
+#[derive(Clone, Debug)]
+pub struct MonoLine {
+    pub track: usize,       // track the notes come from
+    pub channel: u8,        // midi channel the notes come from
+    pub voice: usize,       // index among the lines of the same track and channel
+    pub lines: Vec<ScoreLine>,
+    sounding: Option<u8>,   // key being played while splitting
+    us: u64,                // time of the last line while splitting
+}
+
+impl MonoLine {
+
+    fn new(track: usize, channel: u8, voice: usize) -> Self {
+        Self {
+            track,
+            channel,
+            voice,
+            lines: vec![],
+            sounding: None,
+            us: 0,
+        }
+    }
+
+    fn push(&mut self, us: u64, on: bool, key: u8) {
+        self.lines.push(ScoreLine {
+            on,
+            freq: midi2freq(key, A4_FREQ).round() as u32,
+            delay_us: us - self.us,
+        });
+        self.us = us;
+        self.sounding = if on { Some(key) } else { None };
+    }
+}
+
+/// splits every track and channel of `file` into monophonic lines, in 12 tone
+/// equal temperament with A4 at 440 Hz
+///
+/// a note goes to the first line of its track and channel that is silent, so
+/// overlapping notes spill into extra lines and a track needs as many lines as
+/// it has notes sounding at once
+pub fn split_midi(file: &mut MidiFile) -> Vec<MonoLine> {
+    let mut mono: Vec<MonoLine> = vec![];
+    file.rewind();
+
+    while let Some((us, track, event)) = file.next_track_event() {
+        let channel = event.channel();
+        let same = |line: &MonoLine| line.track == track && line.channel == channel;
+
+        match *event.kind() {
+            MidiEventKind::NoteOn { key, .. } => {
+                // a key struck again while sounding restarts in its own line
+                let sounding = mono.iter().position(|line| same(line) && line.sounding == Some(key));
+                let silent = mono.iter().position(|line| same(line) && line.sounding.is_none());
+                match (sounding, silent) {
+                    (Some(i), _) => {
+                        mono[i].push(us, false, key);
+                        mono[i].push(us, true, key);
+                    }
+                    (None, Some(i)) => mono[i].push(us, true, key),
+                    (None, None) => {
+                        let mut line = MonoLine::new(track, channel, mono.iter().filter(|line| same(line)).count());
+                        line.push(us, true, key);
+                        mono.push(line);
+                    }
+                }
+            }
+            MidiEventKind::NoteOff { key, .. } => {
+                if let Some(line) = mono.iter_mut().find(|line| same(line) && line.sounding == Some(key)) {
+                    line.push(us, false, key);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    file.rewind();
+    mono.sort_by_key(|line| (line.track, line.channel, line.voice));
+    mono
+}
+
+/// writes `lines` as a text score of `status freq delay_us` lines
+pub fn write_score(path: impl AsRef<Path>, lines: &[ScoreLine]) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    for line in lines {
+        writeln!(writer, "{} {} {}", line.on as u8, line.freq, line.delay_us)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
@@ -0,0 +1,225 @@
+use midly::{Fps, Timing};
+
+/// midi tempo until the first tempo change, in microseconds per beat
+pub const DEFAULT_TEMPO: u32 = 500_000;
+
+/// how the ticks of a midi file relate to time
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MidiTiming {
+    /// ticks per beat, the length of a beat following the tempo changes
+    Metrical(u16),
+    /// SMPTE timecode, ticks are a fixed fraction of a frame and tempo changes don't apply
+    Timecode { fps: Fps, ticks_per_frame: u8 },
+}
+
+impl MidiTiming {
+
+    /// frames per second as a fraction, 29.97 being 30000/1001
+    fn fps(fps: Fps) -> (u64, u64) {
+        match fps {
+            Fps::Fps24 => (24, 1),
+            Fps::Fps25 => (25, 1),
+            Fps::Fps29 => (30_000, 1001),
+            Fps::Fps30 => (30, 1),
+        }
+    }
+}
+
+impl From<Timing> for MidiTiming {
+    fn from(timing: Timing) -> Self {
+        match timing {
+            Timing::Metrical(ticks_per_beat) => MidiTiming::Metrical(ticks_per_beat.as_int()),
+            Timing::Timecode(fps, ticks_per_frame) => MidiTiming::Timecode { fps, ticks_per_frame },
+        }
+    }
+}
+
+/// a tempo change and when it happens
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TempoChange {
+    pub tick: u64,
+    pub us: u64,
+    pub tempo: u32,     // microseconds per beat
+}
+
+/// converts between ticks and microseconds for a midi file, following its tempo
+/// changes when the timing is metrical
+///
+/// conversions round to the nearest tick or microsecond
+#[derive(Clone, Debug)]
+pub struct TempoMap {
+    timing: MidiTiming,
+    changes: Vec<TempoChange>,  // in tick order, starting at tick 0
+}
+
+impl TempoMap {
+
+    /// map holding the default tempo only
+    pub fn new(timing: MidiTiming) -> Self {
+        Self {
+            timing,
+            changes: vec![TempoChange { tick: 0, us: 0, tempo: DEFAULT_TEMPO }],
+        }
+    }
+
+    /// map of `(tick, tempo)` changes, in any order
+    pub fn from_changes(timing: MidiTiming, changes: impl IntoIterator<Item = (u64, u32)>) -> Self {
+        let mut changes: Vec<(u64, u32)> = changes.into_iter().collect();
+        changes.sort_by_key(|&(tick, _)| tick);
+        let mut map = Self::new(timing);
+        for (tick, tempo) in changes {
+            map.insert(tick, tempo);
+        }
+        map
+    }
+
+    pub fn timing(&self) -> MidiTiming {
+        self.timing
+    }
+
+    pub fn changes(&self) -> &[TempoChange] {
+        &self.changes
+    }
+
+    /// sets the tempo from `tick` on, dropping the changes that came after it
+    pub fn insert(&mut self, tick: u64, tempo: u32) {
+        let us = self.tick2us(tick);
+        self.changes.retain(|change| change.tick < tick || change.tick == 0);
+        match self.changes.last_mut() {
+            Some(last) if last.tick == tick => last.tempo = tempo,
+            _ => self.changes.push(TempoChange { tick, us, tempo }),
+        }
+    }
+
+    /// the change in effect at `tick`
+    fn change_at_tick(&self, tick: u64) -> TempoChange {
+        let i = self.changes.partition_point(|change| change.tick <= tick);
+        self.changes[i.saturating_sub(1)]
+    }
+
+    /// the change in effect at `us` microseconds
+    fn change_at_us(&self, us: u64) -> TempoChange {
+        let i = self.changes.partition_point(|change| change.us <= us);
+        self.changes[i.saturating_sub(1)]
+    }
+
+    /// tempo in microseconds per beat at `tick`
+    pub fn tempo_at(&self, tick: u64) -> u32 {
+        self.change_at_tick(tick).tempo
+    }
+
+    /// absolute time in microseconds of absolute tick `tick`
+    pub fn tick2us(&self, tick: u64) -> u64 {
+        match self.timing {
+            MidiTiming::Metrical(ticks_per_beat) => {
+                let change = self.change_at_tick(tick);
+                let delta = (tick - change.tick) as u128 * change.tempo as u128;
+                change.us + div_round(delta, ticks_per_beat.max(1) as u128) as u64
+            }
+            MidiTiming::Timecode { fps, ticks_per_frame } => {
+                let (num, den) = MidiTiming::fps(fps);
+                let ticks_per_second = num as u128 * ticks_per_frame.max(1) as u128;
+                div_round(tick as u128 * 1_000_000 * den as u128, ticks_per_second) as u64
+            }
+        }
+    }
+
+    /// absolute tick at `us` microseconds
+    pub fn us2tick(&self, us: u64) -> u64 {
+        match self.timing {
+            MidiTiming::Metrical(ticks_per_beat) => {
+                let change = self.change_at_us(us);
+                let delta = (us - change.us) as u128 * ticks_per_beat as u128;
+                change.tick + div_round(delta, change.tempo.max(1) as u128) as u64
+            }
+            MidiTiming::Timecode { fps, ticks_per_frame } => {
+                let (num, den) = MidiTiming::fps(fps);
+                let ticks_per_second = num as u128 * ticks_per_frame as u128;
+                div_round(us as u128 * ticks_per_second, 1_000_000 * den as u128) as u64
+            }
+        }
+    }
+}
+
+/// `num / den` rounded to the nearest integer
+fn div_round(num: u128, den: u128) -> u128 {
+    (num + den / 2) / den
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 480 ticks per beat at 120 bpm, going to 240 bpm at beat 2 and 60 bpm at beat 4
+    fn metrical() -> TempoMap {
+        TempoMap::from_changes(MidiTiming::Metrical(480), [(1920, 1_000_000), (960, 250_000)])
+    }
+
+    #[test]
+    fn changes_are_timed_in_order() {
+        let map = metrical();
+        let changes: Vec<(u64, u64, u32)> = map.changes().iter().map(|change| (change.tick, change.us, change.tempo)).collect();
+        assert_eq!(changes, [(0, 0, DEFAULT_TEMPO), (960, 1_000_000, 250_000), (1920, 1_500_000, 1_000_000)]);
+        assert_eq!(map.tempo_at(959), DEFAULT_TEMPO);
+        assert_eq!(map.tempo_at(960), 250_000);
+        assert_eq!(map.tempo_at(5000), 1_000_000);
+    }
+
+    #[test]
+    fn metrical_ticks_follow_tempo_changes() {
+        let map = metrical();
+        for (tick, us) in [(0, 0), (480, 500_000), (960, 1_000_000), (1440, 1_250_000), (1920, 1_500_000), (2400, 2_500_000)] {
+            assert_eq!(map.tick2us(tick), us, "tick {tick}");
+            assert_eq!(map.us2tick(us), tick, "{us} us");
+        }
+        // in between ticks round to the nearest
+        assert_eq!(map.us2tick(1_000_300), 961);
+        assert_eq!(map.tick2us(1), 1042);
+    }
+
+    #[test]
+    fn inserting_drops_later_changes() {
+        let mut map = metrical();
+        map.insert(480, 1_000_000);
+        assert_eq!(map.changes().len(), 2);
+        assert_eq!(map.tick2us(960), 1_500_000);
+    }
+
+    #[test]
+    fn timecode_ticks_are_fixed_fractions_of_a_frame() {
+        // 25 frames of 40 ticks make a millisecond tick, tempo changes not applying
+        let map = TempoMap::from_changes(MidiTiming::Timecode { fps: Fps::Fps25, ticks_per_frame: 40 }, [(10, 250_000)]);
+        for (tick, us) in [(0, 0), (1, 1000), (40, 40_000), (2500, 2_500_000)] {
+            assert_eq!(map.tick2us(tick), us, "tick {tick}");
+            assert_eq!(map.us2tick(us), tick, "{us} us");
+        }
+        assert_eq!(map.us2tick(1499), 1);
+        assert_eq!(map.us2tick(1500), 2);
+    }
+
+    #[test]
+    fn drop_frame_timecode_runs_slower() {
+        let map = TempoMap::new(MidiTiming::Timecode { fps: Fps::Fps29, ticks_per_frame: 100 });
+        // 300 frames of 29.97 fps last 10.01 seconds
+        assert_eq!(map.tick2us(30_000), 10_010_000);
+        assert_eq!(map.us2tick(10_010_000), 30_000);
+    }
+
+    #[test]
+    fn conversions_never_go_back() {
+        for map in [metrical(), TempoMap::new(MidiTiming::Timecode { fps: Fps::Fps29, ticks_per_frame: 7 })] {
+            let mut last = 0;
+            for us in (0..3_000_000).step_by(997) {
+                let tick = map.us2tick(us);
+                assert!(tick >= last, "{us} us went back to tick {tick}");
+                last = tick;
+            }
+            let mut last = 0;
+            for tick in 0..3000 {
+                let us = map.tick2us(tick);
+                assert!(us >= last, "tick {tick} went back to {us} us");
+                last = us;
+            }
+        }
+    }
+}
@@ -1,15 +1,22 @@
 use std::fs::File;
 use std::path::Path;
-use wav::BitDepth;
-use crate::io::player::{Player};
-use crate::{AMPLITUDE_MAX, AMPLITUDE_MIN, SAMPLE_RATE};
+use wav::{BitDepth, Header};
+use crate::config::EngineConfig;
+use crate::{AMPLITUDE_MAX, AMPLITUDE_MIN};
+use crate::{Error, Result};
 
-pub fn test() {
-    let mut inp_file = File::open(Path::new("samples/kick.wav")).unwrap();
-    let (header, data) = wav::read(&mut inp_file).unwrap();
-    dbg!(header);
+/// decodes a wav file, a file the decoder can't make sense of being a `WavFormat` error
+fn read(f: &mut File) -> Result<(Header, BitDepth)> {
+    wav::read(f).map_err(|err| Error::WavFormat(err.to_string()))
+}
+
+pub fn test() -> Result<()> {
+    let mut inp_file = File::open(Path::new("samples/kick.wav"))?;
+    let (_, data) = read(&mut inp_file)?;
     let mut buffer = vec![];
-    for sample in data.try_into_eight().unwrap() {
+    let samples = data.try_into_eight()
+        .map_err(|_| Error::WavFormat("expected 8 bit samples".to_string()))?;
+    for sample in samples {
         let mut bit = AMPLITUDE_MIN;
         if sample > 127 {
             bit = AMPLITUDE_MAX;
@@ -20,9 +27,11 @@ pub fn test() {
     // let mut player = Player::new(PlayerKind::KeyboardPlayer);
     // player.play_samples(buffer);
     // player.play_samples(data.as_sixteen().unwrap().to_vec());
+    Ok(())
 }
 
-pub fn donwsample(data: BitDepth) -> Vec<bool> {
+/// thresholds 8 or 16 bit samples into 1 bit ones
+pub fn donwsample(data: BitDepth) -> Result<Vec<bool>> {
     let mut vec = vec![];
     match data {
         BitDepth::Eight(samples) => {
@@ -43,34 +52,30 @@ pub fn donwsample(data: BitDepth) -> Vec<bool> {
                 vec.push(bit);
             }
         }
-        _ => panic!()
+        BitDepth::TwentyFour(_) => return Err(Error::WavFormat("24 bit samples are not supported".to_string())),
+        BitDepth::ThirtyTwoFloat(_) => return Err(Error::WavFormat("32 bit float samples are not supported".to_string())),
+        BitDepth::Empty => return Err(Error::WavFormat("no samples".to_string())),
     }
-    vec
+    Ok(vec)
 }
 
-pub fn resample(samples: Vec<bool>, in_rate: u32, out_rate: u32) -> Vec<bool> {
+/// repeats every sample to go from `in_rate` to `out_rate`
+pub fn resample(samples: Vec<bool>, in_rate: u32, out_rate: u32) -> Result<Vec<bool>> {
+    if in_rate == 0 {
+        return Err(Error::WavFormat("sample rate is 0".to_string()));
+    }
     let mut out_buffer = vec![];
     for sample in samples {
         for _ in 0..out_rate/in_rate {
             out_buffer.push(sample);
         }
     }
-    out_buffer
+    Ok(out_buffer)
 }
 
-pub fn get_sample(mut f: File) -> Vec<bool> {
-    let (header, data) = wav::read(&mut f).unwrap();
+/// reads a wav file as 1 bit samples at the engine's `sample_rate`
+pub fn get_sample(mut f: File, config: &EngineConfig) -> Result<Vec<bool>> {
+    let (header, data) = read(&mut f)?;
     let sampling_rate = header.sampling_rate;
-    resample(donwsample(data), sampling_rate, SAMPLE_RATE)
+    resample(donwsample(data)?, sampling_rate, config.sample_rate)
 }
-
-pub fn play_wav_sample(mut f: File) {
-    let (header, data) = wav::read(&mut f).unwrap();
-    // println!("{:?}", data);
-    let sampling_rate = header.sampling_rate;
-    let buffer = resample(donwsample(data), sampling_rate, SAMPLE_RATE);
-    // let mut player = Player::new(PlayerKind::KeyboardPlayer);
-    for sample in buffer {
-        // player.audio_out(sample);
-    }
-}
\ No newline at end of file
@@ -0,0 +1,88 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use wav::{BitDepth, Header};
+
+use crate::config::EngineConfig;
+use crate::io::audio_out::OutputSink;
+use crate::{Error, Result};
+
+/// writes 8 bit mono samples to a wav file at `rate`
+pub fn write_wav(path: &Path, samples: Vec<u8>, rate: u32) -> Result<()> {
+    let header = Header::new(wav::header::WAV_FORMAT_PCM, 1, rate, 8);
+    let mut writer = BufWriter::new(File::create(path)?);
+    wav::write(header, &BitDepth::Eight(samples), &mut writer)?;
+    Ok(())
+}
+
+/// averages `samples` taken at `in_rate` down to `out_rate`
+///
+/// each output sample is the mean of the input samples it covers, so the 1 bit
+/// stream becomes a multi level signal that plays fine at 44.1 or 48 kHz
+pub fn downsample(samples: &[u8], in_rate: u32, out_rate: u32) -> Result<Vec<u8>> {
+    if in_rate == 0 || out_rate == 0 {
+        return Err(Error::Config(format!("can't downsample from {in_rate} Hz to {out_rate} Hz")));
+    }
+    let in_rate = in_rate as u64;
+    let out_rate = out_rate as u64;
+    let len = samples.len() as u64 * out_rate / in_rate;
+    let mut out = Vec::with_capacity(len as usize);
+
+    for i in 0..len {
+        let start = (i * in_rate / out_rate) as usize;
+        let end = (((i + 1) * in_rate / out_rate) as usize).max(start + 1);
+        let window = &samples[start..end];
+        let sum: u64 = window.iter().map(|&sample| sample as u64).sum();
+        out.push((sum / window.len() as u64) as u8);
+    }
+
+    Ok(out)
+}
+
+/// `OutputSink` that renders to an 8 bit mono wav file at the engine's `sample_rate`
+///
+/// samples are kept in memory and the file is written on `drain`
+pub struct WavSink {
+    path: PathBuf,
+    samples: Vec<u8>,
+    sample_rate: u32,
+    listening: Option<(PathBuf, u32)>,
+}
+
+impl WavSink {
+
+    pub fn new(path: impl AsRef<Path>, config: &EngineConfig) -> Result<Self> {
+        config.validate()?;
+        Ok(Self {
+            path: path.as_ref().to_path_buf(),
+            samples: vec![],
+            sample_rate: config.sample_rate,
+            listening: None,
+        })
+    }
+
+    /// also writes a copy downsampled to `rate` (e.g. 44_100 or 48_000) to `path`
+    pub fn with_listening_copy(mut self, path: impl AsRef<Path>, rate: u32) -> Self {
+        self.listening = Some((path.as_ref().to_path_buf(), rate));
+        self
+    }
+
+    /// samples written since the file was last written out
+    pub fn samples(&self) -> &[u8] {
+        &self.samples
+    }
+}
+
+impl OutputSink for WavSink {
+    fn write(&mut self, buffer: &[u8]) -> Result<()> {
+        self.samples.extend_from_slice(buffer);
+        Ok(())
+    }
+
+    fn drain(&mut self) -> Result<()> {
+        if let Some((path, rate)) = &self.listening {
+            write_wav(path, downsample(&self.samples, self.sample_rate, *rate)?, *rate)?;
+        }
+        write_wav(&self.path, std::mem::take(&mut self.samples), self.sample_rate)
+    }
+}
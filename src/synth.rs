@@ -2,97 +2,97 @@ pub mod voice;
 pub mod channel;
 pub mod drum_machine;
 pub mod sampler;
+pub mod polyphony;
+pub mod modulation;
+pub mod patch;
+pub mod velocity;
+pub mod tuning;
 
-use std::fs::File;
-use std::io::BufReader;
-use alsa::pcm::IO;
-use num_traits::pow;
+use crate::synth::channel::{Channel, MixMode};
+use crate::synth::drum_machine::{DrumMachine, DRUM_CHANNEL};
+use crate::synth::patch::{Patch, PatchBank};
+use crate::synth::polyphony::{AllocationPolicy, TriggerMode};
+use crate::synth::tuning::Tuning;
+use crate::synth::velocity::VelocityCurve;
+use crate::config::EngineConfig;
+use crate::AMPLITUDE_MIN;
 
-use crate::synth::channel::Channel;
-use crate::utils::{read_message, write_buffer};
-use crate::{AMPLITUDE_MAX, AMPLITUDE_MIN, CHANNELS_MAX};
-use crate::SAMPLE_RATE;
-use crate::BUFFER_SIZE;
-
-fn start_channels() -> [Channel; CHANNELS_MAX] {
-    let mut channels = vec![];
-    for _ in 0..CHANNELS_MAX {
-        channels.push(Channel::new());
-    }
-    let channels: [Channel; CHANNELS_MAX] = channels.try_into().unwrap();
-    channels
-}
-
-/// combines the output of up to `CHANNELS_MAX` channels using PIM (Pulse Interleaving Method)
+/// combines the output of up to `channels_max` channels using PIM (Pulse Interleaving Method)
 /// 
-/// like multiple instruments playing different parts together
-#[derive(Debug, Default)]
+/// like multiple instruments playing different parts together, events sent to
+/// channels past `channels_max` being ignored
+#[derive(Debug)]
 pub struct Synth {
-    channels: [Channel; CHANNELS_MAX],
+    config: EngineConfig,
+    channels: Vec<Channel>,
+    bank: PatchBank,
+    drums: DrumMachine,
+    drum_channel: Option<usize>,    // channel whose midi keys hit `drums` instead of playing notes
     current: usize,
     selected: usize
 }
 
+impl Default for Synth {
+    fn default() -> Self {
+        Self::new(&EngineConfig::default())
+    }
+}
+
 impl Synth {
 
-    pub fn new() -> Self {
-        let channels = start_channels();
+    pub fn new(config: &EngineConfig) -> Self {
+        let channels = (0..config.channels_max).map(|_| Channel::new(config)).collect();
         Self {
+            config: *config,
             channels,
+            bank: PatchBank::general_midi(),
+            drums: DrumMachine::general_midi(config),
+            drum_channel: (DRUM_CHANNEL < config.channels_max).then_some(DRUM_CHANNEL),
             current: 0,
             selected: 0,
         }
     }
 
-    /// returns `BUFFER_SIZE` next samples as u8
-    pub fn get_buffer(&mut self) -> [u8; BUFFER_SIZE] {
-        let mut buffer = [0; BUFFER_SIZE];
-        for i in 0..BUFFER_SIZE {
-            buffer[i] = self.get_sample();
-        }
-        buffer
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
     }
 
-    /// returns `BUFFER_SIZE` next samples as bool
-    pub fn get_buffer_bool(&mut self) -> [bool; BUFFER_SIZE] {
-        let mut buffer = [false; BUFFER_SIZE];
-        for i in 0..BUFFER_SIZE {
-            buffer[i] = self.get_sample_bool();
-        }
-        buffer
+    /// returns `buffer_size` next samples as u8
+    pub fn get_buffer(&mut self) -> Vec<u8> {
+        (0..self.config.buffer_size).map(|_| self.get_sample()).collect()
+    }
+
+    /// returns `buffer_size` next samples as bool
+    pub fn get_buffer_bool(&mut self) -> Vec<bool> {
+        (0..self.config.buffer_size).map(|_| self.get_sample_bool()).collect()
     }
 
     /// returns next sample as u8
     pub fn get_sample(&mut self) -> u8 {
-        let mut out = false;
-
-        for i in 0..CHANNELS_MAX {
-            let channel_out = self.channels[i].out();
-            if i == self.current {
-                out = channel_out;
-            }
-        }
-        self.current = (self.current + 1) % CHANNELS_MAX;
-        if out {AMPLITUDE_MAX} else {AMPLITUDE_MIN}
+        if self.get_sample_bool() {self.config.amplitude_max} else {AMPLITUDE_MIN}
     }
 
     /// returns next sample as bool
     pub fn get_sample_bool(&mut self) -> bool {
         let mut out = false;
 
-        for i in 0..CHANNELS_MAX {
-            let channel_out = self.channels[i].out();
+        let drums_out = self.drums.get_sample();
+        for (i, channel) in self.channels.iter_mut().enumerate() {
+            let mut channel_out = channel.out();
+            if Some(i) == self.drum_channel {
+                channel_out |= drums_out;
+            }
             if i == self.current {
                 out = channel_out;
             }
         }
-        self.current = (self.current + 1) % CHANNELS_MAX;
+        self.current = (self.current + 1) % self.channels.len();
         out
     }
 
     /// select channel at index `i`
     pub fn channel_select(&mut self, i: usize) {
-        if i < CHANNELS_MAX {
+        if i < self.channels.len() {
             self.selected = i;
         }
         else {
@@ -100,56 +100,187 @@ impl Synth {
         }
     }
 
+    /// sets the tuning used for midi keys in channel `channel_n`
+    pub fn set_tuning(&mut self, tuning: Tuning, channel_n: usize) {
+        if let Some(channel) = self.channels.get_mut(channel_n) {
+            channel.set_tuning(tuning);
+        }
+    }
+
+    /// sets how notes compete for voices in channel `channel_n`
+    pub fn set_policy(&mut self, policy: AllocationPolicy, channel_n: usize) {
+        if let Some(channel) = self.channels.get_mut(channel_n) {
+            channel.set_policy(policy);
+        }
+    }
+
+    /// sets whether voices in channel `channel_n` restart their wave when moving to a new note
+    pub fn set_trigger(&mut self, trigger: TriggerMode, channel_n: usize) {
+        if let Some(channel) = self.channels.get_mut(channel_n) {
+            channel.set_trigger(trigger);
+        }
+    }
+
+    pub fn bank(&self) -> &PatchBank {
+        &self.bank
+    }
+
+    /// sets the patches midi program changes pick from, the general midi families by default
+    pub fn set_bank(&mut self, bank: PatchBank) {
+        self.bank = bank;
+    }
+
+    /// plays the notes of channel `channel_n` with `patch`, `None` going back to
+    /// plain pulses with the duty each note comes with
+    pub fn set_patch(&mut self, patch: Option<Patch>, channel_n: usize) {
+        if let Some(channel) = self.channels.get_mut(channel_n) {
+            channel.set_patch(patch);
+        }
+    }
+
+    pub fn drums(&self) -> &DrumMachine {
+        &self.drums
+    }
+
+    /// the drum machine, to load drums or change which keys hit them
+    pub fn drums_mut(&mut self) -> &mut DrumMachine {
+        &mut self.drums
+    }
+
+    /// sets the drums the drum channel's midi keys hit
+    pub fn set_drums(&mut self, drums: DrumMachine) {
+        self.drums = drums;
+    }
+
+    pub fn drum_channel(&self) -> Option<usize> {
+        self.drum_channel
+    }
+
+    /// sets the channel whose midi keys hit the drums instead of playing notes,
+    /// channel 10 as in general midi by default, `None` playing notes on every channel
+    pub fn set_drum_channel(&mut self, channel_n: Option<usize>) {
+        self.drum_channel = channel_n;
+    }
+
+    /// plays the notes of channel `channel_n` with the bank's patch for midi program `program`
+    ///
+    /// the drum channel has no patches and ignores it
+    pub fn program_change(&mut self, program: u8, channel_n: usize) {
+        if Some(channel_n) == self.drum_channel {
+            return;
+        }
+        let patch = self.bank.get(program).clone();
+        if let Some(channel) = self.channels.get_mut(channel_n) {
+            channel.set_patch(Some(patch));
+        }
+    }
+
+    /// turns on midi key `key` in selected channel, using the channel's tuning
+    ///
+    /// on the drum channel it hits the drum the key is mapped to instead
+    pub fn key_on(&mut self, key: u8, duty: f32, channel_n: usize) {
+        if Some(channel_n) == self.drum_channel {
+            self.drums.key_on(key);
+            return;
+        }
+        if let Some(channel) = self.channels.get_mut(channel_n) {
+            channel.key_on(key, duty);
+        }
+    }
+
+    /// turns on midi key `key` struck with `velocity` in channel `channel_n`, as loud
+    /// as the channel's velocity curve makes it
+    ///
+    /// on the drum channel it hits the drum the key is mapped to instead
+    pub fn key_on_velocity(&mut self, key: u8, velocity: u8, duty: f32, channel_n: usize) {
+        if Some(channel_n) == self.drum_channel {
+            self.drums.key_on(key);
+            return;
+        }
+        if let Some(channel) = self.channels.get_mut(channel_n) {
+            channel.key_on_velocity(key, velocity, duty);
+        }
+    }
+
+    /// turns off midi key `key` in selected channel, drums ringing out on their own
+    pub fn key_off(&mut self, key: u8, channel_n: usize) {
+        if Some(channel_n) == self.drum_channel {
+            return;
+        }
+        if let Some(channel) = self.channels.get_mut(channel_n) {
+            channel.key_off(key);
+        }
+    }
+
+    /// releases every note in channel `channel_n`
+    pub fn all_notes_off(&mut self, channel_n: usize) {
+        if let Some(channel) = self.channels.get_mut(channel_n) {
+            channel.all_notes_off();
+        }
+    }
+
+    /// bends every voice in channel `channel_n` by `bend`, from -8192 to 8191
+    pub fn pitch_bend(&mut self, bend: i16, channel_n: usize) {
+        if let Some(channel) = self.channels.get_mut(channel_n) {
+            channel.pitch_bend(bend);
+        }
+    }
+
+    /// sets how many semitones the pitch bend reaches either way in channel `channel_n`
+    pub fn set_bend_range(&mut self, semitones: f32, channel_n: usize) {
+        if let Some(channel) = self.channels.get_mut(channel_n) {
+            channel.set_bend_range(semitones);
+        }
+    }
+
+    /// sets the vibrato the modulation wheel brings in on channel `channel_n`,
+    /// reaching `depth` semitones either way at `rate` Hz
+    pub fn set_vibrato(&mut self, depth: f32, rate: f32, channel_n: usize) {
+        if let Some(channel) = self.channels.get_mut(channel_n) {
+            channel.set_vibrato(depth, rate);
+        }
+    }
+
+    /// sends a midi control change to channel `channel_n`
+    pub fn control_change(&mut self, controller: u8, value: u8, channel_n: usize) {
+        if let Some(channel) = self.channels.get_mut(channel_n) {
+            channel.control_change(controller, value);
+        }
+    }
+
     /// turns on note in selected channel
-    pub fn note_on(&mut self, freq: u32, duty: f32, channel_n: usize) {
-        self.channels[channel_n].note_on(freq, duty);
+    pub fn note_on(&mut self, freq: f32, duty: f32, channel_n: usize) {
+        if let Some(channel) = self.channels.get_mut(channel_n) {
+            channel.note_on(freq, duty);
+        }
+    }
+
+    /// turns on note struck with `velocity` in channel `channel_n`, as loud as the
+    /// channel's velocity curve makes it
+    pub fn note_on_velocity(&mut self, freq: f32, velocity: u8, duty: f32, channel_n: usize) {
+        if let Some(channel) = self.channels.get_mut(channel_n) {
+            channel.note_on_velocity(freq, velocity, duty);
+        }
+    }
+
+    /// sets how note velocity maps to pulse width in channel `channel_n`
+    pub fn set_velocity_curve(&mut self, curve: VelocityCurve, channel_n: usize) {
+        if let Some(channel) = self.channels.get_mut(channel_n) {
+            channel.set_velocity_curve(curve);
+        }
+    }
+
+    /// sets how the voices of channel `channel_n` are combined
+    pub fn set_mix(&mut self, mix: MixMode, channel_n: usize) {
+        if let Some(channel) = self.channels.get_mut(channel_n) {
+            channel.set_mix(mix);
+        }
     }
 
     /// turn off note in selected channel
-    pub fn note_off(&mut self, freq: u32, channel_n: usize) {
-        self.channels[channel_n].note_off(freq);
-    }
-
-    // / TODO replace this with something better
-    // pub fn play_files(&mut self, readers: &mut [BufReader<File>; CHANNELS_MAX], io: &IO<u8>) {
-    //     for i in 0..CHANNELS_MAX {
-    //         let parts = read_message(&mut readers[i]);
-    //         self.channels[i].message.0 = parts[0];
-    //         self.channels[i].message.1 = parts[1];
-    //         let delay = parts[2];
-    //         self.channels[i].message.2 = (delay as u64 * (SAMPLE_RATE as u64 / BUFFER_SIZE as u64) / pow(10, 6) as u64) as u32;
-    //     }
-
-    //     loop {
-    //         for i in 0..CHANNELS_MAX {
-    //             let (status, freq, counter) = self.channels[i].message;
-    //             if counter == 0 {
-    //                 // println!("{:?}", freqs);
-    //                 if status == 0 {
-    //                     self.channels[i].note_off(freq);
-    //                 } else {
-    //                     self.channels[i].note_on(freq, 0.1)
-    //                 }
-    
-    //                 let parts = read_message(&mut readers[i]);
-    
-    //                 if parts.len() == 0 {
-    //                     break;
-    //                 }
-    
-    //                 self.channels[i].message.0 = parts[0];
-    //                 self.channels[i].message.1 = parts[1];
-    //                 let delay = parts[2];
-    //                 self.channels[i].message.2 = (delay as u64 * (SAMPLE_RATE as u64 / BUFFER_SIZE as u64) / pow(10, 6) as u64) as u32;
-    
-    //                 // println!("{}, {}, {}", status, freq, delay);
-    //             } else {
-    //                 self.channels[i].message.2 -= 1;
-    
-    //             }
-
-    //         }
-    //         write_buffer(self.synth_out_buffer(), io);
-    //     }
-    // }
-}
\ No newline at end of file
+    pub fn note_off(&mut self, freq: f32, channel_n: usize) {
+        if let Some(channel) = self.channels.get_mut(channel_n) {
+            channel.note_off(freq);
+        }
+    }
+}
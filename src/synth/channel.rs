@@ -1,64 +1,388 @@
 use std::collections::VecDeque;
-use std::fs::File;
-use std::io::BufReader;
-use alsa::pcm::IO;
-use num_traits::pow;
 use rand::Rng;
 
-use super::voice::Voice;
-use crate::utils::{buffer_or, read_message, write_buffer};
-use crate::{AMPLITUDE_MAX, AMPLITUDE_MIN, VOICES_MAX};
-use crate::SAMPLE_RATE;
-use crate::BUFFER_SIZE;
+use super::modulation::{control_period, Modulation};
+use super::patch::Patch;
+use super::polyphony::{AllocationPolicy, Polyphony, TriggerMode};
+use super::tuning::Tuning;
+use super::velocity::VelocityCurve;
+use crate::config::EngineConfig;
+use crate::AMPLITUDE_MIN;
 
+/// midi controllers a channel responds to
+const CC_MODULATION: u8 = 1;
+const CC_DATA_ENTRY: u8 = 6;
+const CC_VOLUME: u8 = 7;
+const CC_EXPRESSION: u8 = 11;
+const CC_DATA_ENTRY_LSB: u8 = 38;
+const CC_SUSTAIN: u8 = 64;
+const CC_NRPN_LSB: u8 = 98;
+const CC_NRPN_MSB: u8 = 99;
+const CC_RPN_LSB: u8 = 100;
+const CC_RPN_MSB: u8 = 101;
+const CC_ALL_SOUND_OFF: u8 = 120;
+const CC_RESET_CONTROLLERS: u8 = 121;
+const CC_ALL_NOTES_OFF: u8 = 123;
 
-/// combines the output of up to `VOICES_MAX` voices using PPM (Pin Pulse Method)
+/// volume and expression a channel starts at, full so notes keep the duty they
+/// are played with until told otherwise
+const VOLUME_DEFAULT: u8 = 127;
+const EXPRESSION_DEFAULT: u8 = 127;
+
+/// registered parameter setting the pitch bend range
+const RPN_BEND_RANGE: (u8, u8) = (0, 0);
+
+/// width of a pin pulse in microseconds, at full loudness
+pub const PIN_WIDTH: f32 = 20.;
+
+/// how a channel combines its voices into one bit
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MixMode {
+    /// voices play full pulses ORed together, chords smearing once their duties overlap
+    #[default]
+    Or,
+    /// PPM (Pin Pulse Method): each voice plays a pin pulse `width` microseconds wide
+    /// once per period, narrower the quieter its note, so pulses of different voices
+    /// rarely overlap and the notes of a chord stay apart
+    Pin { width: f32 },
+}
+
+impl MixMode {
+
+    /// pin pulses `PIN_WIDTH` microseconds wide
+    pub fn pin() -> Self {
+        MixMode::Pin { width: PIN_WIDTH }
+    }
+}
+
+/// combines the output of up to `voices_max` voices following a `MixMode`
 /// 
 /// like an instrument playing multiple notes simultaneously
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct Channel {
-    voices: Vec<Voice>,
+    voices: Polyphony,
+    tuning: Tuning,
+    modulation: Modulation,
+    rpn: Option<(u8, u8)>,      // registered parameter data entry goes to, msb and lsb
+    volume: u8,                 // midi channel volume
+    expression: u8,             // midi expression, a fraction of the volume
+    sustain: bool,              // whether the sustain pedal is down
+    sustained: Vec<f32>,        // notes released while the pedal is down
+    patch: Option<Patch>,       // instrument overriding the duty notes are played with
+    velocity_curve: VelocityCurve,
+    mix: MixMode,
+    period: u32,                // samples between control updates
+    counter: u32,               // samples since the last control update
+    buffer_size: usize,
+    sample_rate: u32,
+}
+
+impl Default for Channel {
+    fn default() -> Self {
+        Self::new(&EngineConfig::default())
+    }
 }
 
 impl Channel {
 
-    pub fn new() -> Self {
+    pub fn new(config: &EngineConfig) -> Self {
         Self {
-            voices: vec![],
+            voices: Polyphony::new(config),
+            tuning: Tuning::default(),
+            modulation: Modulation::new(config),
+            rpn: None,
+            volume: VOLUME_DEFAULT,
+            expression: EXPRESSION_DEFAULT,
+            sustain: false,
+            sustained: vec![],
+            patch: None,
+            velocity_curve: VelocityCurve::default(),
+            mix: MixMode::default(),
+            period: control_period(config.sample_rate),
+            counter: 0,
+            buffer_size: config.buffer_size,
+            sample_rate: config.sample_rate,
+        }
+    }
+
+    /// sets how held notes compete for the channel's voices
+    pub fn set_policy(&mut self, policy: AllocationPolicy) {
+        self.voices.set_policy(policy);
+    }
+
+    /// sets whether voices moving to a new note restart their wave
+    pub fn set_trigger(&mut self, trigger: TriggerMode) {
+        self.voices.set_trigger(trigger);
+    }
+
+    pub fn tuning(&self) -> &Tuning {
+        &self.tuning
+    }
+
+    /// sets how midi keys played on this channel map to frequencies
+    pub fn set_tuning(&mut self, tuning: Tuning) {
+        self.tuning = tuning;
+    }
+
+    /// turns on midi key `key`, tuned by the channel's `Tuning`
+    pub fn key_on(&mut self, key: u8, duty: f32) {
+        if let Some(freq) = self.tuning.key2freq(key) {
+            self.note_on(freq, duty);
         }
     }
 
-    /// sets an available voice's `freq` and `duty` and turns it on
-    pub fn note_on(&mut self, freq: u32, duty: f32) {
-        if self.voices.len() < VOICES_MAX {
-            self.voices.push(Voice::new(freq, duty))
+    /// turns on midi key `key` struck with `velocity`, tuned by the channel's `Tuning`
+    /// and as loud as the channel's `VelocityCurve` makes it
+    pub fn key_on_velocity(&mut self, key: u8, velocity: u8, duty: f32) {
+        if let Some(freq) = self.tuning.key2freq(key) {
+            self.note_on_velocity(freq, velocity, duty);
         }
-        panic!()
     }
 
-    /// turns off voice currently playing `freq`
-    pub fn note_off(&mut self, freq: u32){
-        self.voices.retain(|voice| voice.freq() != freq);
+    /// turns off midi key `key`
+    pub fn key_off(&mut self, key: u8) {
+        if let Some(freq) = self.tuning.key2freq(key) {
+            self.note_off(freq);
+        }
+    }
+
+    pub fn mix(&self) -> MixMode {
+        self.mix
+    }
+
+    /// sets how the channel's voices are combined, pin widths given in microseconds
+    pub fn set_mix(&mut self, mix: MixMode) {
+        let pin = match mix {
+            MixMode::Or => None,
+            MixMode::Pin { width } => Some(width * 1e-6 * self.sample_rate as f32),
+        };
+        self.voices.set_pin(pin);
+        self.mix = mix;
+    }
+
+    pub fn patch(&self) -> Option<&Patch> {
+        self.patch.as_ref()
+    }
+
+    /// plays every note with `patch` from now on, `None` going back to plain pulses
+    /// with the duty each note comes with
+    pub fn set_patch(&mut self, patch: Option<Patch>) {
+        let default = Patch::default();
+        let sound = patch.as_ref().unwrap_or(&default);
+        self.voices.set_envelope(sound.envelope);
+        self.modulation.set_arpeggio(&sound.arpeggio, sound.arpeggio_rate);
+        self.modulation.set_vibrato(sound.vibrato);
+        self.modulation.set_transpose(sound.octave as f32 * 12.);
+        self.patch = patch;
+        self.retune();
     }
 
-    /// returns `BUFFER_SIZE` next samples
-    pub fn out_buffer(&mut self) -> [bool; BUFFER_SIZE] {
-        let mut buffer = [false; BUFFER_SIZE];
+    /// holds a note at `freq` with `duty`, giving it a voice according to the channel's `AllocationPolicy`
+    ///
+    /// the channel's patch, if any, decides the duty instead
+    pub fn note_on(&mut self, freq: f32, duty: f32) {
+        self.note_on_level(freq, duty, 1.);
+    }
+
+    /// same as `note_on`, struck with `velocity` and as loud as the channel's
+    /// `VelocityCurve` makes it
+    pub fn note_on_velocity(&mut self, freq: f32, velocity: u8, duty: f32) {
+        let level = self.velocity_curve.level(velocity);
+        self.note_on_level(freq, duty, level);
+    }
 
-        for i in 0..self.voices.len() {
-            buffer = buffer_or(buffer, self.voices[i].out_buffer())
+    fn note_on_level(&mut self, freq: f32, duty: f32, level: f32) {
+        let duty = self.patch.as_ref().map_or(duty, |patch| patch.duty);
+        self.sustained.retain(|&sustained| sustained != freq);
+        self.voices.note_on_level(freq, duty, level);
+    }
+
+    pub fn velocity_curve(&self) -> &VelocityCurve {
+        &self.velocity_curve
+    }
+
+    /// sets how the velocity of the following notes maps to their pulse width
+    pub fn set_velocity_curve(&mut self, curve: VelocityCurve) {
+        self.velocity_curve = curve;
+    }
+
+    /// releases the note at `freq`, or once the sustain pedal is up if it is down
+    pub fn note_off(&mut self, freq: f32){
+        if self.sustain {
+            if !self.sustained.contains(&freq) {
+                self.sustained.push(freq);
+            }
+        } else {
+            self.voices.note_off(freq);
         }
+    }
 
-        buffer
+    /// releases every note at once, sustained or not
+    pub fn all_notes_off(&mut self) {
+        self.sustained.clear();
+        self.voices.clear();
+    }
+
+    /// releases every note, leaving them to the sustain pedal if it is down
+    pub fn release_all(&mut self) {
+        let held: Vec<f32> = self.voices.held().collect();
+        for freq in held {
+            self.note_off(freq);
+        }
+    }
+
+    pub fn sustain(&self) -> bool {
+        self.sustain
+    }
+
+    /// presses or lifts the sustain pedal, lifting it releasing the notes it held
+    pub fn set_sustain(&mut self, sustain: bool) {
+        self.sustain = sustain;
+        if !sustain {
+            for freq in std::mem::take(&mut self.sustained) {
+                self.voices.note_off(freq);
+            }
+        }
+    }
+
+    pub fn volume(&self) -> u8 {
+        self.volume
+    }
+
+    /// sets the midi channel volume, from 0 to 127
+    pub fn set_volume(&mut self, volume: u8) {
+        self.volume = volume.min(127);
+        self.update_loudness();
+    }
+
+    pub fn expression(&self) -> u8 {
+        self.expression
+    }
+
+    /// sets the midi expression, from 0 to 127
+    pub fn set_expression(&mut self, expression: u8) {
+        self.expression = expression.min(127);
+        self.update_loudness();
+    }
+
+    /// narrows the voices' pulses following the volume and expression, each of
+    /// them scaling the amplitude by its square as general midi recommends
+    fn update_loudness(&mut self) {
+        let volume = self.volume as f32 / 127.;
+        let expression = self.expression as f32 / 127.;
+        self.voices.set_gain((volume * expression).powi(2));
+    }
+
+    /// puts back the controllers a midi reset all controllers message resets, the
+    /// volume staying as it is
+    pub fn reset_controllers(&mut self) {
+        self.modulation.set_bend(0.);
+        self.modulation.set_depth(0.);
+        self.rpn = None;
+        self.set_sustain(false);
+        self.set_expression(EXPRESSION_DEFAULT);
+        self.retune();
+    }
+
+    pub fn modulation(&self) -> &Modulation {
+        &self.modulation
+    }
+
+    /// retunes the voices to the current pitch bend and vibrato
+    fn retune(&mut self) {
+        self.voices.set_detune(self.modulation.ratio());
+    }
+
+    /// bends every voice by `bend`, from -8192 (all the way down) to 8191 (all the way up)
+    pub fn pitch_bend(&mut self, bend: i16) {
+        self.modulation.set_bend(bend as f32 / 8192.);
+        self.retune();
+    }
+
+    /// sets how many semitones the pitch bend reaches either way
+    pub fn set_bend_range(&mut self, semitones: f32) {
+        self.modulation.set_bend_range(semitones);
+        self.retune();
+    }
+
+    /// sets the modulation wheel position, from 0 to 127
+    pub fn modulation_wheel(&mut self, value: u8) {
+        self.modulation.set_depth(value as f32 / 127.);
+        self.retune();
+    }
+
+    /// sets the vibrato the modulation wheel brings in, reaching `depth` semitones
+    /// either way at `rate` Hz
+    pub fn set_vibrato(&mut self, depth: f32, rate: f32) {
+        self.modulation.set_vibrato_depth(depth);
+        self.modulation.set_vibrato_rate(rate);
+        self.retune();
+    }
+
+    /// responds to a midi control change, ignoring controllers the channel has no use for
+    ///
+    /// volume and expression set the loudness by narrowing the pulses, all notes
+    /// off leaves sustained notes to the pedal while all sound off silences them too
+    ///
+    /// the pitch bend range is set through registered parameter 0, semitones on
+    /// data entry and cents on its lsb
+    pub fn control_change(&mut self, controller: u8, value: u8) {
+        match controller {
+            CC_MODULATION => self.modulation_wheel(value),
+            CC_VOLUME => self.set_volume(value),
+            CC_EXPRESSION => self.set_expression(value),
+            CC_SUSTAIN => self.set_sustain(value >= 64),
+            CC_ALL_SOUND_OFF => self.all_notes_off(),
+            CC_RESET_CONTROLLERS => self.reset_controllers(),
+            CC_ALL_NOTES_OFF => self.release_all(),
+            CC_RPN_MSB => self.rpn = Some((value, self.rpn.map_or(0x7F, |rpn| rpn.1))),
+            CC_RPN_LSB => self.rpn = Some((self.rpn.map_or(0x7F, |rpn| rpn.0), value)),
+            CC_NRPN_MSB | CC_NRPN_LSB => self.rpn = None,
+            CC_DATA_ENTRY if self.rpn == Some(RPN_BEND_RANGE) => {
+                let cents = self.modulation.bend_range().fract();
+                self.set_bend_range(value as f32 + cents);
+            }
+            CC_DATA_ENTRY_LSB if self.rpn == Some(RPN_BEND_RANGE) => {
+                let semitones = self.modulation.bend_range().trunc();
+                self.set_bend_range(semitones + value.min(99) as f32 / 100.);
+            }
+            _ => {}
+        }
+    }
+
+    /// returns `buffer_size` next samples
+    pub fn out_buffer(&mut self) -> Vec<bool> {
+        (0..self.buffer_size).map(|_| self.out()).collect()
+    }
+
+    /// moves pitch and loudness on by one control period
+    fn update(&mut self) {
+        if let Some(ratio) = self.modulation.update() {
+            self.voices.set_detune(ratio);
+        }
+        self.voices.advance(self.period);
     }
 
     /// returns next sample
     pub fn out(&mut self) -> bool {
+        self.counter += 1;
+        if self.counter >= self.period {
+            self.counter = 0;
+            self.update();
+        }
+
         let mut out = false;
-        for i in 0..self.voices.len() {
-            out |= self.voices[i].out()
+        for voice in self.voices.voices_mut() {
+            out |= voice.out()
+        }
+
+        // noise only sounds along with notes
+        match self.patch {
+            Some(ref patch) if patch.noise > 0. && !self.voices.is_empty() && rand::random::<f32>() < patch.noise => {
+                rand::random()
+            }
+            _ => out,
         }
-        out
     }
 }
 
@@ -84,11 +408,11 @@ impl NoiseChannel {
         (0..size).map(|_| if rng.gen_bool(0.5) { 1.0 } else { -1.0 }).collect()
     }
 
-    pub fn generate_pink_noise(size: usize) -> Vec<u8> {
+    pub fn generate_pink_noise(size: usize, amplitude: u8) -> Vec<u8> {
         let white_noise = Self::generate_white_noise(size);
         let mut pink_noise = vec![0.0; size];
-        let mut b = [0.02109238, 0.07113478, 0.68873558, -0.02813463, -0.02260048];
-        let mut a = [1.0, -2.81337002, 2.69422456, -0.89651434, 0.02109238];
+        let b = [0.02109238, 0.07113478, 0.68873558, -0.02813463, -0.02260048];
+        let a = [1.0, -2.81337002, 2.69422456, -0.89651434, 0.02109238];
         
         for i in 0..size {
             let mut pink_sample = white_noise[i];
@@ -100,7 +424,7 @@ impl NoiseChannel {
             pink_noise[i] = pink_sample;
         }
 
-        pink_noise.into_iter().map(|sample| if sample >= 0.0 { AMPLITUDE_MAX } else { AMPLITUDE_MIN }).collect()
+        pink_noise.into_iter().map(|sample| if sample >= 0.0 { amplitude } else { AMPLITUDE_MIN }).collect()
     }
 
     fn apply_low_pass_filter(input: &[f64], alpha: f64) -> Vec<f64> {
@@ -112,21 +436,23 @@ impl NoiseChannel {
         output
     }
 
-    pub fn generate_brown_noise(size: usize, alpha: f64) -> Vec<u8> {
+    pub fn generate_brown_noise(size: usize, alpha: f64, amplitude: u8) -> Vec<u8> {
         let white_noise = Self::generate_white_noise(size);
         let filtered_noise = Self::apply_low_pass_filter(&white_noise, alpha);
         
         // Convert to 1-bit
-        filtered_noise.into_iter().map(|sample| if sample >= 0.0 { AMPLITUDE_MAX } else { AMPLITUDE_MIN }).collect()
+        filtered_noise.into_iter().map(|sample| if sample >= 0.0 { amplitude } else { AMPLITUDE_MIN }).collect()
     }
 }
 
 
+#[allow(dead_code)]
 struct FIRBandPassFilter {
     coefficients: Vec<f64>,
     buffer: VecDeque<f64>,
 }
 
+#[allow(dead_code)]
 impl FIRBandPassFilter {
     fn new(low_cut: f64, high_cut: f64, sample_rate: f64, filter_order: usize) -> Self {
         let coefficients = FIRBandPassFilter::calculate_coefficients(low_cut, high_cut, sample_rate, filter_order);
@@ -142,12 +468,12 @@ impl FIRBandPassFilter {
         let mut h = vec![0.0; filter_order];
         let m = filter_order as isize - 1;
 
-        for i in 0..filter_order {
+        for (i, coefficient) in h.iter_mut().enumerate() {
             if i as isize == m / 2 {
-                h[i] = 2.0 * (fc2 - fc1);
+                *coefficient = 2.0 * (fc2 - fc1);
             } else {
                 let x = i as isize - m / 2;
-                h[i] = sinc(2.0 * fc2 * x as f64) - sinc(2.0 * fc1 * x as f64);
+                *coefficient = sinc(2.0 * fc2 * x as f64) - sinc(2.0 * fc1 * x as f64);
             }
         }
 
@@ -163,10 +489,12 @@ impl FIRBandPassFilter {
     }
 }
 
+#[allow(dead_code)]
 fn binary_to_bipolar(bit: u8) -> f64 {
     2.0 * bit as f64 - 1.0
 }
 
+#[allow(dead_code)]
 fn bipolar_to_binary(bit: f64) -> u8 {
     if bit >= 0.0 { 1 } else { 0 }
 }
\ No newline at end of file
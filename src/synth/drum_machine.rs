@@ -1,5 +1,25 @@
+use std::fmt::Debug;
 
+use super::sampler;
+use crate::config::EngineConfig;
 
+/// channel general midi plays percussion on, channel 10 counting from 1
+pub const DRUM_CHANNEL: usize = 9;
+
+/// something the drum machine can hit
+pub trait Drum: Debug + Send {
+    /// starts the sound from the beginning
+    fn hit(&mut self);
+
+    /// whether the sound is still playing
+    fn is_sounding(&self) -> bool;
+
+    /// returns next sample
+    fn get_sample(&mut self) -> bool;
+}
+
+/// plays a recorded 1 bit sample each time it's hit
+#[derive(Debug)]
 pub struct DrumVoice {
     sample: Vec<bool>,
     counter: usize,
@@ -17,7 +37,7 @@ impl DrumVoice {
 
     pub fn hit(&mut self) {
         self.counter = 0;
-        self.on_hit = true;
+        self.on_hit = !self.sample.is_empty();
     }
 
     pub fn get_sample(&mut self) -> bool {
@@ -33,36 +53,124 @@ impl DrumVoice {
     }
 }
 
+impl Drum for DrumVoice {
+    fn hit(&mut self) {
+        DrumVoice::hit(self);
+    }
+
+    fn is_sounding(&self) -> bool {
+        self.on_hit
+    }
+
+    fn get_sample(&mut self) -> bool {
+        DrumVoice::get_sample(self)
+    }
+}
+
+/// combines the output of its drums using PIM (Pulse Interleaving Method), taking
+/// turns among the ones sounding
+///
+/// midi keys are mapped to drums, so a key hits the drum it's mapped to
+#[derive(Debug)]
 pub struct DrumMachine {
-    voices: Vec<DrumVoice>,
+    voices: Vec<Box<dyn Drum>>,
+    keys: Vec<Option<usize>>,   // drum each midi key hits
     current: usize,
 }
 
+impl Default for DrumMachine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl DrumMachine {
 
+    /// machine with no drums and no keys mapped
     pub fn new() -> Self {
         Self {
             voices: vec![],
+            keys: vec![None; 128],
             current: 0,
         }
     }
 
-    pub fn load_voice(&mut self, voice: DrumVoice) {
-        self.voices.push(voice);
-        self.voices[0].hit();
+    /// synthesized kit with the general midi percussion keys mapped to it
+    pub fn general_midi(config: &EngineConfig) -> Self {
+        let one_shot = |length, freq, duty, density| {
+            sampler::DrumVoice::one_shot(length, freq, duty, config).with_density(density)
+        };
+
+        let mut drums = Self::new();
+        let kick = drums.load_voice(one_shot(0.12, 60., 0.5, 1.));
+        let snare = drums.load_voice(one_shot(0.15, 200., 0.45, 0.6));
+        let closed_hat = drums.load_voice(one_shot(0.04, 7000., 0.5, 0.5));
+        let open_hat = drums.load_voice(one_shot(0.3, 7000., 0.5, 0.5));
+        let low_tom = drums.load_voice(one_shot(0.2, 100., 0.5, 0.9));
+        let mid_tom = drums.load_voice(one_shot(0.2, 140., 0.5, 0.9));
+        let high_tom = drums.load_voice(one_shot(0.2, 190., 0.5, 0.9));
+        let crash = drums.load_voice(one_shot(0.8, 5000., 0.5, 0.4));
+        let ride = drums.load_voice(one_shot(0.6, 3500., 0.5, 0.7));
+
+        let map = [
+            (35, kick), (36, kick),
+            (37, snare), (38, snare), (39, snare), (40, snare),
+            (42, closed_hat), (44, closed_hat), (46, open_hat),
+            (41, low_tom), (43, low_tom), (45, mid_tom), (47, mid_tom), (48, high_tom), (50, high_tom),
+            (49, crash), (52, crash), (55, crash), (57, crash),
+            (51, ride), (53, ride), (59, ride),
+        ];
+        for (key, voice) in map {
+            drums.map_key(key, Some(voice));
+        }
+        drums
+    }
+
+    /// adds a drum, returning its index
+    pub fn load_voice(&mut self, voice: impl Drum + 'static) -> usize {
+        self.voices.push(Box::new(voice));
+        self.voices.len() - 1
+    }
+
+    /// makes midi key `key` hit drum `voice`, or nothing
+    pub fn map_key(&mut self, key: u8, voice: Option<usize>) {
+        self.keys[key as usize & 0x7F] = voice;
+    }
+
+    /// drum midi key `key` hits, if any
+    pub fn key2voice(&self, key: u8) -> Option<usize> {
+        self.keys[key as usize & 0x7F]
+    }
+
+    /// hits drum `voice`
+    pub fn hit(&mut self, voice: usize) {
+        if let Some(voice) = self.voices.get_mut(voice) {
+            voice.hit();
+        }
+    }
+
+    /// hits the drum midi key `key` is mapped to
+    pub fn key_on(&mut self, key: u8) {
+        if let Some(voice) = self.key2voice(key) {
+            self.hit(voice);
+        }
     }
 
     pub fn get_sample(&mut self) -> bool {
+        let n = self.voices.len();
+        // the next drum sounding takes this sample
+        let slot = (0..n).map(|i| (self.current + i) % n).find(|&i| self.voices[i].is_sounding());
+
         let mut out = false;
-        if self.voices.len() > 0 {
-            for i in 0..self.voices.len() {
-                let channel_out: bool = self.voices[i].get_sample();
-                if i == self.current {
-                    out = channel_out;
-                }
+        for (i, voice) in self.voices.iter_mut().enumerate() {
+            let voice_out = voice.get_sample();
+            if Some(i) == slot {
+                out = voice_out;
             }
-            self.current = (self.current + 1) % self.voices.len();
+        }
+        if let Some(i) = slot {
+            self.current = (i + 1) % n;
         }
         out
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,171 @@
+use std::f32::consts::TAU;
+
+use crate::config::EngineConfig;
+
+/// pitch bend range in semitones either way, the general midi default
+pub const BEND_RANGE: f32 = 2.;
+
+/// vibrato depth in semitones either way with the modulation wheel all the way up
+pub const VIBRATO_DEPTH: f32 = 0.5;
+
+/// vibrato speed in Hz
+pub const VIBRATO_RATE: f32 = 5.5;
+
+/// times per second pitch and loudness changing over time are updated
+pub const CONTROL_RATE: u32 = 1000;
+
+/// samples between control updates at `sample_rate`
+pub fn control_period(sample_rate: u32) -> u32 {
+    (sample_rate / CONTROL_RATE).max(1)
+}
+
+/// pitch offset shared by every voice of a channel, from pitch bend, vibrato,
+/// arpeggio and transposition
+///
+/// vibrato and arpeggio move in steps of one control period, as retuning the
+/// voices every sample would cost more than the voices themselves
+#[derive(Clone, Debug)]
+pub struct Modulation {
+    bend: f32,          // pitch bend position, from -1 to 1
+    bend_range: f32,    // semitones the pitch bend reaches either way
+    depth: f32,         // modulation wheel position, from 0 to 1
+    vibrato: f32,       // semitones the vibrato reaches either way with the wheel all the way down
+    vibrato_depth: f32, // semitones the modulation wheel adds to the vibrato at full depth
+    phase: f32,         // position within the vibrato's period, 1 being a full period
+    step: f32,          // phase increment per control update
+    arpeggio: Vec<i8>,  // semitones cycled through, empty for none
+    arp_phase: f32,     // position within the arpeggio, 1 being one note
+    arp_step: f32,      // arpeggio phase increment per control update
+    transpose: f32,     // semitones every note is shifted by
+    period: u32,        // samples between control updates
+    sample_rate: u32,
+}
+
+impl Default for Modulation {
+    fn default() -> Self {
+        Self::new(&EngineConfig::default())
+    }
+}
+
+impl Modulation {
+
+    pub fn new(config: &EngineConfig) -> Self {
+        let mut modulation = Self {
+            bend: 0.,
+            bend_range: BEND_RANGE,
+            depth: 0.,
+            vibrato: 0.,
+            vibrato_depth: VIBRATO_DEPTH,
+            phase: 0.,
+            step: 0.,
+            arpeggio: vec![],
+            arp_phase: 0.,
+            arp_step: 0.,
+            transpose: 0.,
+            period: control_period(config.sample_rate),
+            sample_rate: config.sample_rate,
+        };
+        modulation.set_vibrato_rate(VIBRATO_RATE);
+        modulation
+    }
+
+    /// `rate` times per second as an increment per control update
+    fn rate2step(&self, rate: f32) -> f32 {
+        rate.max(0.) * self.period as f32 / self.sample_rate as f32
+    }
+
+    pub fn bend(&self) -> f32 {
+        self.bend
+    }
+
+    /// sets the pitch bend position, from -1 (all the way down) to 1 (all the way up)
+    pub fn set_bend(&mut self, bend: f32) {
+        self.bend = bend.clamp(-1., 1.);
+    }
+
+    pub fn bend_range(&self) -> f32 {
+        self.bend_range
+    }
+
+    /// sets how many semitones the pitch bend reaches either way
+    pub fn set_bend_range(&mut self, semitones: f32) {
+        self.bend_range = semitones.max(0.);
+    }
+
+    pub fn depth(&self) -> f32 {
+        self.depth
+    }
+
+    /// sets the modulation wheel position, from 0 to 1
+    pub fn set_depth(&mut self, depth: f32) {
+        self.depth = depth.clamp(0., 1.);
+    }
+
+    /// sets how many semitones the vibrato reaches either way with the modulation
+    /// wheel all the way down
+    pub fn set_vibrato(&mut self, semitones: f32) {
+        self.vibrato = semitones.max(0.);
+    }
+
+    /// sets how many semitones the modulation wheel adds to the vibrato at full depth
+    pub fn set_vibrato_depth(&mut self, semitones: f32) {
+        self.vibrato_depth = semitones.max(0.);
+    }
+
+    /// sets the vibrato speed to `rate` Hz
+    pub fn set_vibrato_rate(&mut self, rate: f32) {
+        self.step = self.rate2step(rate);
+    }
+
+    /// cycles every note through `semitones` above it, `rate` of them per second
+    ///
+    /// an empty `semitones` turns the arpeggio off
+    pub fn set_arpeggio(&mut self, semitones: &[i8], rate: f32) {
+        self.arpeggio = semitones.to_vec();
+        self.arp_phase = 0.;
+        self.arp_step = self.rate2step(rate);
+    }
+
+    pub fn transpose(&self) -> f32 {
+        self.transpose
+    }
+
+    /// shifts every note by `semitones`
+    pub fn set_transpose(&mut self, semitones: f32) {
+        self.transpose = semitones;
+    }
+
+    /// semitones the vibrato currently reaches either way
+    fn vibrato_semitones(&self) -> f32 {
+        self.vibrato + self.depth * self.vibrato_depth
+    }
+
+    /// whether the pitch moves over time
+    pub fn is_moving(&self) -> bool {
+        self.vibrato_semitones() > 0. || self.arpeggio.len() > 1
+    }
+
+    /// frequency ratio the voices are currently shifted by
+    pub fn ratio(&self) -> f32 {
+        let vibrato = self.vibrato_semitones() * (TAU * self.phase).sin();
+        let arpeggio = match self.arpeggio.len() {
+            0 => 0,
+            len => self.arpeggio[self.arp_phase as usize % len],
+        };
+        let semitones = self.bend * self.bend_range + vibrato + arpeggio as f32 + self.transpose;
+        2f32.powf(semitones / 12.)
+    }
+
+    /// advances by one control period, returning the new ratio when the pitch moved
+    pub fn update(&mut self) -> Option<f32> {
+        if !self.is_moving() {
+            self.phase = 0.;
+            return None;
+        }
+        self.phase = (self.phase + self.step).fract();
+        if !self.arpeggio.is_empty() {
+            self.arp_phase = (self.arp_phase + self.arp_step) % self.arpeggio.len() as f32;
+        }
+        Some(self.ratio())
+    }
+}
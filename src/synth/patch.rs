@@ -0,0 +1,168 @@
+/// how a note's loudness moves from its start, as a fraction of its duty's
+///
+/// voices stop as soon as their note is released, so there is no release stage
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Envelope {
+    pub attack: f32,    // seconds to rise from silence to full loudness
+    pub decay: f32,     // seconds to fall from full loudness to the sustain level
+    pub sustain: f32,   // loudness held until the note is released, from 0 to 1
+}
+
+impl Default for Envelope {
+    fn default() -> Self {
+        Self::flat()
+    }
+}
+
+impl Envelope {
+
+    pub fn new(attack: f32, decay: f32, sustain: f32) -> Self {
+        Self {
+            attack: attack.max(0.),
+            decay: decay.max(0.),
+            sustain: sustain.clamp(0., 1.),
+        }
+    }
+
+    /// full loudness from start to end
+    pub fn flat() -> Self {
+        Self::new(0., 0., 1.)
+    }
+
+    /// whether the loudness never changes
+    pub fn is_flat(&self) -> bool {
+        self.attack == 0. && (self.decay == 0. || self.sustain == 1.)
+    }
+
+    /// loudness `secs` seconds into a note, from 0 to 1
+    pub fn level(&self, secs: f32) -> f32 {
+        if secs < self.attack {
+            return secs / self.attack;
+        }
+        let secs = secs - self.attack;
+        if secs < self.decay {
+            return 1. - (1. - self.sustain) * secs / self.decay;
+        }
+        self.sustain
+    }
+}
+
+/// a 1 bit instrument, the way a channel plays its notes
+#[derive(Clone, Debug, PartialEq)]
+pub struct Patch {
+    pub name: String,
+    pub duty: f32,          // duty cycle every note is played with
+    pub envelope: Envelope,
+    pub arpeggio: Vec<i8>,  // semitones above the note cycled through, empty for none
+    pub arpeggio_rate: f32, // arpeggio notes per second
+    pub vibrato: f32,       // semitones the vibrato reaches either way before the modulation wheel
+    pub noise: f32,         // chance of each sample being a random bit while notes sound, from 0 to 1
+    pub octave: i8,         // octaves every note is shifted by
+}
+
+impl Default for Patch {
+    fn default() -> Self {
+        Self::new("square", 0.5)
+    }
+}
+
+impl Patch {
+
+    /// plain pulse wave with `duty`
+    pub fn new(name: &str, duty: f32) -> Self {
+        Self {
+            name: name.to_string(),
+            duty,
+            envelope: Envelope::flat(),
+            arpeggio: vec![],
+            arpeggio_rate: 0.,
+            vibrato: 0.,
+            noise: 0.,
+            octave: 0,
+        }
+    }
+
+    pub fn with_envelope(mut self, attack: f32, decay: f32, sustain: f32) -> Self {
+        self.envelope = Envelope::new(attack, decay, sustain);
+        self
+    }
+
+    pub fn with_arpeggio(mut self, semitones: &[i8], rate: f32) -> Self {
+        self.arpeggio = semitones.to_vec();
+        self.arpeggio_rate = rate;
+        self
+    }
+
+    pub fn with_vibrato(mut self, semitones: f32) -> Self {
+        self.vibrato = semitones;
+        self
+    }
+
+    pub fn with_noise(mut self, noise: f32) -> Self {
+        self.noise = noise.clamp(0., 1.);
+        self
+    }
+
+    pub fn with_octave(mut self, octave: i8) -> Self {
+        self.octave = octave;
+        self
+    }
+}
+
+/// 128 patches, one per midi program
+#[derive(Clone, Debug, PartialEq)]
+pub struct PatchBank {
+    patches: Vec<Patch>,
+}
+
+impl Default for PatchBank {
+    fn default() -> Self {
+        Self::general_midi()
+    }
+}
+
+impl PatchBank {
+
+    /// every program playing `patch`
+    pub fn new(patch: Patch) -> Self {
+        Self {
+            patches: vec![patch; 128],
+        }
+    }
+
+    /// one patch per general midi family of 8 programs, so pianos, basses, leads
+    /// and pads each sound their own way
+    pub fn general_midi() -> Self {
+        let families = [
+            Patch::new("piano", 0.5).with_envelope(0., 1.2, 0.35),
+            Patch::new("chromatic percussion", 0.25).with_envelope(0., 0.4, 0.).with_octave(1),
+            Patch::new("organ", 0.5),
+            Patch::new("guitar", 0.3).with_envelope(0., 0.8, 0.4),
+            Patch::new("bass", 0.5).with_envelope(0., 0.3, 0.7),
+            Patch::new("strings", 0.35).with_envelope(0.08, 0., 1.).with_vibrato(0.15),
+            Patch::new("ensemble", 0.35).with_envelope(0.15, 0., 1.).with_vibrato(0.1),
+            Patch::new("brass", 0.25).with_envelope(0.03, 0.2, 0.8),
+            Patch::new("reed", 0.15).with_vibrato(0.1),
+            Patch::new("pipe", 0.45).with_envelope(0.05, 0., 1.).with_vibrato(0.1),
+            Patch::new("synth lead", 0.125).with_vibrato(0.1),
+            Patch::new("synth pad", 0.4).with_envelope(0.3, 0., 1.).with_arpeggio(&[0, 12], 15.),
+            Patch::new("synth effects", 0.25).with_arpeggio(&[0, 4, 7], 50.),
+            Patch::new("ethnic", 0.2).with_envelope(0., 0.5, 0.2),
+            Patch::new("percussive", 0.5).with_envelope(0., 0.15, 0.).with_noise(0.3),
+            Patch::new("sound effects", 0.5).with_noise(0.6),
+        ];
+        Self {
+            patches: (0..128).map(|program| families[program / 8].clone()).collect(),
+        }
+    }
+
+    /// patch of midi program `program`
+    pub fn get(&self, program: u8) -> &Patch {
+        &self.patches[program as usize & 0x7F]
+    }
+
+    /// makes midi program `program` play `patch`
+    pub fn set(&mut self, program: u8, patch: Patch) {
+        self.patches[program as usize & 0x7F] = patch;
+    }
+}
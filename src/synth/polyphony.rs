@@ -0,0 +1,440 @@
+use std::f32::consts::PI;
+
+use super::patch::Envelope;
+use super::voice::Voice;
+use crate::config::EngineConfig;
+
+/// decides which notes sound when more notes are held than there are voices
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AllocationPolicy {
+    /// a new note steals the voice of the longest sounding note
+    #[default]
+    StealOldest,
+    /// the lowest held notes sound, a new note only steals from a higher one
+    LowestNote,
+    /// the highest held notes sound, a new note only steals from a lower one
+    HighestNote,
+    /// monophonic, the most recent held note sounds, falling back to the previous one on release
+    LastNote,
+}
+
+/// what a voice does when it moves from one note to another
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TriggerMode {
+    /// the wave restarts from the beginning of its period
+    #[default]
+    Retrigger,
+    /// the wave keeps its phase and only changes pitch
+    Legato,
+}
+
+/// duty cycle whose pulse wave has `gain` times the fundamental of one with `duty`
+///
+/// the fundamental of a pulse wave grows with `sin(pi * duty)`, so duties past
+/// one half are narrowed from the other side
+fn gain2duty(duty: f32, gain: f32) -> f32 {
+    if gain >= 1. {
+        return duty;
+    }
+    let narrow = ((PI * duty).sin() * gain).asin() / PI;
+    if duty > 0.5 { 1. - narrow } else { narrow }
+}
+
+/// a held note
+#[derive(Clone, Copy, Debug)]
+struct Note {
+    freq: f32,
+    duty: f32,
+    level: f32,     // loudness the note was struck with, from 0 to 1
+    order: u64,     // when the note was pressed, higher is more recent
+}
+
+/// a voice and the note it is playing
+#[derive(Debug)]
+struct Slot {
+    voice: Voice,
+    note: Note,
+    age: u64,       // samples since the voice took the note
+}
+
+/// assigns held notes to up to `voices_max` voices following an `AllocationPolicy`
+///
+/// notes that lose their voice stay held, so priority policies can give them a
+/// voice back once a higher priority note is released
+#[derive(Debug)]
+pub struct Polyphony {
+    slots: Vec<Slot>,
+    held: Vec<Note>,
+    policy: AllocationPolicy,
+    trigger: TriggerMode,
+    detune: f32,        // frequency ratio every voice plays its note at
+    gain: f32,          // loudness of every voice relative to its note's duty
+    envelope: Envelope, // loudness of every voice over time, on top of `gain`
+    pin: Option<f32>,   // width in samples of pin pulses at full loudness, `None` to play duties
+    config: EngineConfig,
+    counter: u64,
+}
+
+impl Default for Polyphony {
+    fn default() -> Self {
+        Self::new(&EngineConfig::default())
+    }
+}
+
+impl Polyphony {
+
+    pub fn new(config: &EngineConfig) -> Self {
+        Self {
+            slots: vec![],
+            held: vec![],
+            policy: AllocationPolicy::default(),
+            trigger: TriggerMode::default(),
+            detune: 1.,
+            gain: 1.,
+            envelope: Envelope::flat(),
+            pin: None,
+            config: *config,
+            counter: 0,
+        }
+    }
+
+    pub fn policy(&self) -> AllocationPolicy {
+        self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: AllocationPolicy) {
+        self.policy = policy;
+    }
+
+    pub fn trigger(&self) -> TriggerMode {
+        self.trigger
+    }
+
+    pub fn set_trigger(&mut self, trigger: TriggerMode) {
+        self.trigger = trigger;
+    }
+
+    pub fn detune(&self) -> f32 {
+        self.detune
+    }
+
+    /// shifts every voice to `ratio` times the frequency of its note, keeping the
+    /// phase of their waves
+    pub fn set_detune(&mut self, ratio: f32) {
+        self.detune = ratio;
+        for slot in self.slots.iter_mut() {
+            slot.voice.set_freq(slot.note.freq * ratio);
+        }
+    }
+
+    pub fn gain(&self) -> f32 {
+        self.gain
+    }
+
+    /// makes every voice `gain` times as loud as its note by narrowing its pulse,
+    /// keeping the phase of their waves
+    pub fn set_gain(&mut self, gain: f32) {
+        self.gain = gain.clamp(0., 1.);
+        self.update_duties();
+    }
+
+    pub fn envelope(&self) -> Envelope {
+        self.envelope
+    }
+
+    /// shapes the loudness of every note over time with `envelope`
+    pub fn set_envelope(&mut self, envelope: Envelope) {
+        self.envelope = envelope;
+        self.update_duties();
+    }
+
+    pub fn pin(&self) -> Option<f32> {
+        self.pin
+    }
+
+    /// makes every voice play pin pulses `width` samples wide at full loudness,
+    /// narrower the quieter their note, instead of its duty, or go back to duties
+    /// with `None`
+    pub fn set_pin(&mut self, width: Option<f32>) {
+        self.pin = width.map(|width| width.max(0.));
+        self.update_duties();
+    }
+
+    /// loudness of `note` at `age` samples into it, from 0 to 1
+    fn level(&self, note: &Note, age: u64) -> f32 {
+        let secs = age as f32 / self.config.sample_rate as f32;
+        self.gain * note.level * self.envelope.level(secs)
+    }
+
+    /// duty a voice plays `note` with at `age` samples into it
+    fn duty(&self, note: &Note, age: u64) -> f32 {
+        gain2duty(note.duty, self.level(note, age))
+    }
+
+    /// pin width a voice plays `note` with at `age` samples into it, if playing pins
+    ///
+    /// the fundamental of a narrow pulse grows with its width, so the width scales
+    /// with loudness as is
+    fn pin_width(&self, note: &Note, age: u64) -> Option<f32> {
+        self.pin.map(|width| width * self.level(note, age))
+    }
+
+    /// sets every voice's duty or pin width after the gain, envelope or mix changed
+    fn update_duties(&mut self) {
+        for i in 0..self.slots.len() {
+            let (note, age) = (self.slots[i].note, self.slots[i].age);
+            let (duty, pin) = (self.duty(&note, age), self.pin_width(&note, age));
+            let voice = &mut self.slots[i].voice;
+            voice.set_duty(duty);
+            voice.set_pin(pin);
+        }
+    }
+
+    /// moves every note `samples` samples on through the envelope
+    pub fn advance(&mut self, samples: u32) {
+        for slot in self.slots.iter_mut() {
+            slot.age += samples as u64;
+        }
+        if !self.envelope.is_flat() {
+            self.update_duties();
+        }
+    }
+
+    /// maximum number of voices sounding at once
+    fn capacity(&self) -> usize {
+        match self.policy {
+            AllocationPolicy::LastNote => 1,
+            _ => self.config.voices_max,
+        }
+    }
+
+    /// voices currently sounding
+    pub fn voices_mut(&mut self) -> impl Iterator<Item = &mut Voice> {
+        self.slots.iter_mut().map(|slot| &mut slot.voice)
+    }
+
+    pub fn voices(&self) -> impl Iterator<Item = &Voice> {
+        self.slots.iter().map(|slot| &slot.voice)
+    }
+
+    /// number of voices currently sounding
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// moves the voice in slot `i` to `note`
+    fn assign(&mut self, i: usize, note: Note) {
+        let (duty, pin) = (self.duty(&note, 0), self.pin_width(&note, 0));
+        let slot = &mut self.slots[i];
+        slot.note = note;
+        slot.age = 0;
+        slot.voice.set_pin(pin);
+        slot.voice.set(note.freq * self.detune, duty);
+        if self.trigger == TriggerMode::Retrigger {
+            slot.voice.retrigger();
+        }
+    }
+
+    /// index of the slot a new `note` may take over, if any
+    fn victim(&self, note: &Note) -> Option<usize> {
+        let slots = self.slots.iter().enumerate();
+        match self.policy {
+            AllocationPolicy::StealOldest | AllocationPolicy::LastNote => {
+                slots.min_by_key(|(_, slot)| slot.note.order).map(|(i, _)| i)
+            }
+            AllocationPolicy::LowestNote => {
+                slots.max_by(|(_, a), (_, b)| a.note.freq.total_cmp(&b.note.freq))
+                    .filter(|(_, slot)| note.freq < slot.note.freq)
+                    .map(|(i, _)| i)
+            }
+            AllocationPolicy::HighestNote => {
+                slots.min_by(|(_, a), (_, b)| a.note.freq.total_cmp(&b.note.freq))
+                    .filter(|(_, slot)| note.freq > slot.note.freq)
+                    .map(|(i, _)| i)
+            }
+        }
+    }
+
+    /// held note that should take a freed voice, if any
+    fn waiting(&self) -> Option<Note> {
+        let waiting = self.held.iter()
+            .filter(|note| !self.slots.iter().any(|slot| slot.note.freq == note.freq))
+            .copied();
+        match self.policy {
+            AllocationPolicy::StealOldest => None,
+            AllocationPolicy::LowestNote => waiting.min_by(|a, b| a.freq.total_cmp(&b.freq)),
+            AllocationPolicy::HighestNote => waiting.max_by(|a, b| a.freq.total_cmp(&b.freq)),
+            AllocationPolicy::LastNote => waiting.max_by_key(|note| note.order),
+        }
+    }
+
+    /// holds a note at `freq` and gives it a voice if the policy allows
+    pub fn note_on(&mut self, freq: f32, duty: f32) {
+        self.note_on_level(freq, duty, 1.);
+    }
+
+    /// same as `note_on`, the note being `level` times as loud, from 0 to 1
+    pub fn note_on_level(&mut self, freq: f32, duty: f32, level: f32) {
+        // pressing a note that is already held restarts it
+        self.held.retain(|note| note.freq != freq);
+        self.slots.retain(|slot| slot.note.freq != freq);
+
+        self.counter += 1;
+        let note = Note {
+            freq,
+            duty,
+            level: level.clamp(0., 1.),
+            order: self.counter,
+        };
+        self.held.push(note);
+
+        if self.slots.len() < self.capacity() {
+            let mut voice = Voice::new(freq * self.detune, self.duty(&note, 0), &self.config);
+            voice.set_pin(self.pin_width(&note, 0));
+            self.slots.push(Slot {
+                voice,
+                note,
+                age: 0,
+            });
+        } else if let Some(i) = self.victim(&note) {
+            self.assign(i, note);
+        }
+    }
+
+    /// releases the note at `freq`, handing its voice to a waiting note if the policy allows
+    pub fn note_off(&mut self, freq: f32) {
+        self.held.retain(|note| note.freq != freq);
+
+        if let Some(i) = self.slots.iter().position(|slot| slot.note.freq == freq) {
+            match self.waiting() {
+                Some(note) => self.assign(i, note),
+                None => {
+                    self.slots.remove(i);
+                }
+            }
+        }
+    }
+
+    /// frequencies of the held notes, whether they have a voice or not
+    pub fn held(&self) -> impl Iterator<Item = f32> + '_ {
+        self.held.iter().map(|note| note.freq)
+    }
+
+    /// releases every note
+    pub fn clear(&mut self) {
+        self.held.clear();
+        self.slots.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn polyphony(voices_max: usize, policy: AllocationPolicy) -> Polyphony {
+        let config = EngineConfig { voices_max, ..EngineConfig::default() };
+        let mut voices = Polyphony::new(&config);
+        voices.set_policy(policy);
+        voices
+    }
+
+    /// frequencies sounding, lowest first
+    fn sounding(voices: &Polyphony) -> Vec<f32> {
+        let mut freqs: Vec<f32> = voices.voices().map(|voice| voice.freq()).collect();
+        freqs.sort_by(f32::total_cmp);
+        freqs
+    }
+
+    #[test]
+    fn steal_oldest_takes_the_longest_sounding_voice() {
+        let mut voices = polyphony(2, AllocationPolicy::StealOldest);
+        voices.note_on(200., 0.5);
+        voices.note_on(300., 0.5);
+        voices.note_on(100., 0.5);
+        assert_eq!(sounding(&voices), [100., 300.]);
+        voices.note_on(400., 0.5);
+        assert_eq!(sounding(&voices), [100., 400.]);
+
+        // stolen notes stay silent once a voice frees up
+        voices.note_off(400.);
+        assert_eq!(sounding(&voices), [100.]);
+    }
+
+    #[test]
+    fn lowest_note_only_steals_from_higher_notes() {
+        let mut voices = polyphony(2, AllocationPolicy::LowestNote);
+        voices.note_on(200., 0.5);
+        voices.note_on(300., 0.5);
+        voices.note_on(400., 0.5);
+        assert_eq!(sounding(&voices), [200., 300.]);
+        voices.note_on(100., 0.5);
+        assert_eq!(sounding(&voices), [100., 200.]);
+
+        // the lowest note waiting gets the freed voice back
+        voices.note_off(100.);
+        assert_eq!(sounding(&voices), [200., 300.]);
+    }
+
+    #[test]
+    fn highest_note_only_steals_from_lower_notes() {
+        let mut voices = polyphony(2, AllocationPolicy::HighestNote);
+        voices.note_on(300., 0.5);
+        voices.note_on(200., 0.5);
+        voices.note_on(100., 0.5);
+        assert_eq!(sounding(&voices), [200., 300.]);
+        voices.note_on(400., 0.5);
+        assert_eq!(sounding(&voices), [300., 400.]);
+
+        voices.note_off(400.);
+        assert_eq!(sounding(&voices), [200., 300.]);
+    }
+
+    #[test]
+    fn last_note_falls_back_to_the_previous_one() {
+        let mut voices = polyphony(4, AllocationPolicy::LastNote);
+        voices.note_on(100., 0.5);
+        voices.note_on(300., 0.5);
+        voices.note_on(200., 0.5);
+        assert_eq!(sounding(&voices), [200.]);
+        voices.note_off(200.);
+        assert_eq!(sounding(&voices), [300.]);
+        voices.note_off(100.);
+        assert_eq!(sounding(&voices), [300.]);
+        voices.note_off(300.);
+        assert!(voices.is_empty());
+    }
+
+    #[test]
+    fn pressing_a_held_note_again_restarts_it() {
+        let mut voices = polyphony(2, AllocationPolicy::StealOldest);
+        voices.note_on(100., 0.5);
+        voices.note_on(200., 0.5);
+        voices.note_on(100., 0.5);
+        voices.note_on(300., 0.5);
+        assert_eq!(sounding(&voices), [100., 300.]);
+    }
+
+    /// whether a voice moved to a new note halfway through its period starts high again
+    fn restarts(trigger: TriggerMode) -> bool {
+        let mut voices = polyphony(1, AllocationPolicy::StealOldest);
+        voices.set_trigger(trigger);
+        voices.note_on(440., 0.5);
+        let period = EngineConfig::default().sample_rate as f32 / 440.;
+        for _ in 0..(period * 0.75) as usize {
+            voices.voices_mut().for_each(|voice| { voice.out(); });
+        }
+        voices.note_on(441., 0.5);
+        let out = voices.voices_mut().all(|voice| voice.out());
+        out
+    }
+
+    #[test]
+    fn legato_keeps_the_phase_of_the_wave() {
+        assert!(restarts(TriggerMode::Retrigger));
+        assert!(!restarts(TriggerMode::Legato));
+    }
+}
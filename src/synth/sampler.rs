@@ -1,11 +1,9 @@
 use num_traits::pow;
 use rand;
 
-use crate::io::wav_reader;
-use crate::DUTY_MAX;
-use crate::SAMPLE_RATE;
-use crate::BUFFER_SIZE;
+use crate::config::EngineConfig;
 
+use super::drum_machine::Drum;
 use super::voice::Voice;
 
 // enum DrumVoice {
@@ -15,39 +13,84 @@ use super::voice::Voice;
 //     Cymbal(Vec<u8>),
 // }
 
+/// synthesized drum, a decaying pulse wave with noise
+///
+/// plays on every beat, or only when hit for a one shot drum
+#[derive(Debug)]
 pub struct DrumVoice {
     voice: Voice,
-    freq: u32,
+    freq: f32,
     duty: f32,
     pos: u32,
-    samples_per_beat: u32,
+    samples_per_beat: u32,  // samples per beat, or per hit for a one shot drum
+    samples_per_ms: u32,
     beat: u32,
     decay: f32,
     env: f32,
+    density: f32,           // chance of a high sample staying high, lower being noisier
+    one_shot: bool,         // whether it only plays when hit
+    sounding: bool,
 }
 
 impl DrumVoice {
 
-    pub fn new(bpm: u32, freq: u32, duty: f32) -> Self {
-        let samples_per_beat = SAMPLE_RATE * 60 / bpm;
-        let voice = Voice::new(freq, duty);
+    pub fn new(bpm: u32, freq: f32, duty: f32, config: &EngineConfig) -> Self {
+        let samples_per_beat = config.sample_rate * 60 / bpm;
+        let samples_per_ms = (config.sample_rate / 1000).max(1);
+        let voice = Voice::new(freq, duty, config);
         let decay = 1. - pow(0.1, 7);
         Self {
             freq,
             duty,
             voice,
             samples_per_beat,
+            samples_per_ms,
             decay,
             pos: 0,
             beat: 0,
             env: 1.,
+            density: 0.9,
+            one_shot: false,
+            sounding: true,
         }
     }
 
+    /// drum sounding for `length` seconds each time it's hit, silent until then
+    pub fn one_shot(length: f32, freq: f32, duty: f32, config: &EngineConfig) -> Self {
+        let mut drum = Self::new(60, freq, duty, config);
+        drum.samples_per_beat = (length.max(0.) * config.sample_rate as f32) as u32;
+        drum.one_shot = true;
+        drum.sounding = false;
+        drum
+    }
+
+    /// same drum with `density` as the chance of a high sample staying high
+    pub fn with_density(mut self, density: f32) -> Self {
+        self.density = density.clamp(0., 1.);
+        self
+    }
+
+    /// restarts the drum from the beginning of its beat
+    pub fn hit(&mut self) {
+        self.env = 1.;
+        self.pos = 0;
+        self.voice.set(self.freq, self.duty);
+        self.voice.retrigger();
+        self.sounding = true;
+    }
+
+    #[allow(dead_code)]
     fn load_samples(&mut self) {
     }
 
     pub fn get_sample(&mut self) -> bool {
+        if !self.sounding {
+            return false;
+        }
+        if self.pos >= self.samples_per_beat && self.one_shot {
+            self.sounding = false;
+            return false;
+        }
         if self.pos >= self.samples_per_beat {
             self.beat = (self.beat + 1) % 16;
             self.env = 1.;
@@ -57,8 +100,8 @@ impl DrumVoice {
 
         self.env *= self.decay;
 
-        if self.pos % (SAMPLE_RATE / 1000) == 0 {
-            let freq = (self.voice.freq() as f32 * self.env) as u32;
+        if self.pos.is_multiple_of(self.samples_per_ms) {
+            let freq = self.voice.freq() * self.env;
             let duty = self.voice.duty() * self.env;
             self.voice.set(freq, duty);
         }
@@ -66,12 +109,24 @@ impl DrumVoice {
         
         let mut sample = self.voice.out();
 
-        let noise = 0.9;
-
-        sample = sample & (rand::random::<f32>() < noise);
+        sample &= rand::random::<f32>() < self.density;
 
         self.pos += 1;
 
-        return sample;
+        sample
     }
-}
\ No newline at end of file
+}
+
+impl Drum for DrumVoice {
+    fn hit(&mut self) {
+        DrumVoice::hit(self);
+    }
+
+    fn is_sounding(&self) -> bool {
+        self.sounding
+    }
+
+    fn get_sample(&mut self) -> bool {
+        DrumVoice::get_sample(self)
+    }
+}
@@ -0,0 +1,320 @@
+/// default reference frequency of A4 in Hz
+pub const A4_FREQ: f32 = 440.;
+
+/// midi note number of A4
+pub const A4_KEY: u8 = 69;
+
+/// midi note number of middle C
+pub const C4_KEY: u8 = 60;
+
+/// returns the 12 tone equal tempered frequency of midi note `key` in Hz,
+/// with A4 tuned to `a4` Hz
+pub fn midi2freq(key: u8, a4: f32) -> f32 {
+    a4 * 2f32.powf((key as f32 - A4_KEY as f32) / 12.)
+}
+
+/// returns the 12 tone equal tempered midi note nearest to `freq` Hz, with A4
+/// tuned to `a4` Hz
+pub fn freq2midi(freq: f32, a4: f32) -> u8 {
+    (A4_KEY as f32 + 12. * (freq / a4).log2()).round().clamp(0., 127.) as u8
+}
+
+/// converts a frequency ratio to cents
+pub fn ratio2cents(ratio: f64) -> f64 {
+    1200. * ratio.log2()
+}
+
+/// the pitches of one period of a scale, in cents above its first degree
+///
+/// degree 0 is always 0 cents and is not stored, the last stored degree is the
+/// period the scale repeats at (1200 cents for octave based scales)
+#[derive(Clone, Debug)]
+pub struct Scale {
+    description: String,
+    cents: Vec<f64>,
+}
+
+impl Scale {
+
+    /// `cents` holds degrees 1 to n, the last one being the period
+    pub fn new(description: &str, cents: Vec<f64>) -> Self {
+        Self {
+            description: description.to_string(),
+            cents,
+        }
+    }
+
+    /// builds a scale from frequency ratios, the last one being the period
+    pub fn from_ratios(description: &str, ratios: &[(u32, u32)]) -> Self {
+        let cents = ratios.iter()
+            .map(|&(num, den)| ratio2cents(num as f64 / den as f64))
+            .collect();
+        Self::new(description, cents)
+    }
+
+    /// `divisions` equal steps per octave
+    pub fn equal(divisions: u32) -> Self {
+        let divisions = divisions.max(1);
+        let step = 1200. / divisions as f64;
+        let cents = (1..=divisions).map(|i| i as f64 * step).collect();
+        Self::new(&format!("{divisions} equal divisions of the octave"), cents)
+    }
+
+    /// 5-limit just intonation chromatic scale
+    pub fn just_intonation() -> Self {
+        Self::from_ratios("5-limit just intonation", &[
+            (16, 15), (9, 8), (6, 5), (5, 4), (4, 3), (45, 32),
+            (3, 2), (8, 5), (5, 3), (9, 5), (15, 8), (2, 1),
+        ])
+    }
+
+    /// chromatic scale built from pure fifths
+    pub fn pythagorean() -> Self {
+        Self::from_ratios("Pythagorean", &[
+            (256, 243), (9, 8), (32, 27), (81, 64), (4, 3), (729, 512),
+            (3, 2), (128, 81), (27, 16), (16, 9), (243, 128), (2, 1),
+        ])
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// number of degrees in one period
+    pub fn len(&self) -> usize {
+        self.cents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cents.is_empty()
+    }
+
+    /// size of the period in cents
+    pub fn period(&self) -> f64 {
+        self.cents.last().copied().unwrap_or(1200.)
+    }
+
+    /// pitch of any degree in cents, degrees past the period wrap into the next one
+    pub fn degree2cents(&self, degree: i32) -> f64 {
+        if self.cents.is_empty() {
+            return 0.;
+        }
+        let len = self.cents.len() as i32;
+        let periods = degree.div_euclid(len);
+        let index = degree.rem_euclid(len) as usize;
+        let within = if index == 0 { 0. } else { self.cents[index - 1] };
+        periods as f64 * self.period() + within
+    }
+}
+
+impl Default for Scale {
+    fn default() -> Self {
+        Self::equal(12)
+    }
+}
+
+/// maps midi keys to scale degrees, following the Scala keyboard mapping model
+#[derive(Clone, Debug)]
+pub struct KeyboardMapping {
+    first_key: u8,          // lowest key that is retuned
+    last_key: u8,           // highest key that is retuned
+    middle_key: u8,         // key playing degree 0 of the scale
+    reference_key: u8,      // key tuned to `reference_freq`
+    reference_freq: f32,    // frequency of `reference_key` in Hz
+    octave_degree: i32,     // scale degree the mapping repeats at, 0 for the scale's size
+    map: Vec<Option<i32>>,  // degree of each key in a mapping period, `None` for unmapped keys
+}
+
+impl KeyboardMapping {
+
+    /// maps consecutive keys to consecutive degrees, with `middle_key` on degree 0
+    pub fn linear(middle_key: u8, reference_key: u8, reference_freq: f32) -> Self {
+        Self {
+            first_key: 0,
+            last_key: 127,
+            middle_key,
+            reference_key,
+            reference_freq,
+            octave_degree: 0,
+            map: vec![],
+        }
+    }
+
+    /// `map` gives the degree of each key in a mapping period starting at `middle_key`,
+    /// every period moving the degrees up by `octave_degree`, or by the size of the
+    /// scale when it is 0
+    pub fn new(
+        first_key: u8,
+        last_key: u8,
+        middle_key: u8,
+        reference_key: u8,
+        reference_freq: f32,
+        octave_degree: i32,
+        map: Vec<Option<i32>>,
+    ) -> Self {
+        Self {
+            first_key,
+            last_key,
+            middle_key,
+            reference_key,
+            reference_freq,
+            octave_degree,
+            map,
+        }
+    }
+
+    /// retunes `key` to `freq` Hz
+    pub fn with_reference(mut self, key: u8, freq: f32) -> Self {
+        self.reference_key = key;
+        self.reference_freq = freq;
+        self
+    }
+
+    pub fn reference_freq(&self) -> f32 {
+        self.reference_freq
+    }
+
+    /// scale degree played by `key` in a scale of `scale_len` degrees, `None` if the
+    /// key is unmapped
+    pub fn key2degree(&self, key: u8, scale_len: usize) -> Option<i32> {
+        let offset = key as i32 - self.middle_key as i32;
+        if self.map.is_empty() {
+            return Some(offset);
+        }
+        let len = self.map.len() as i32;
+        let periods = offset.div_euclid(len);
+        let degree = self.map[offset.rem_euclid(len) as usize]?;
+        let octave_degree = match self.octave_degree {
+            0 => scale_len as i32,
+            octave_degree => octave_degree,
+        };
+        Some(periods * octave_degree + degree)
+    }
+}
+
+impl Default for KeyboardMapping {
+    fn default() -> Self {
+        Self::linear(C4_KEY, A4_KEY, A4_FREQ)
+    }
+}
+
+/// how a channel turns midi keys into frequencies
+///
+/// defaults to 12 tone equal temperament with A4 at 440 Hz
+#[derive(Clone, Debug, Default)]
+pub struct Tuning {
+    scale: Scale,
+    mapping: KeyboardMapping,
+}
+
+impl Tuning {
+
+    pub fn new(scale: Scale, mapping: KeyboardMapping) -> Self {
+        Self {
+            scale,
+            mapping,
+        }
+    }
+
+    /// 12 tone equal temperament
+    pub fn equal_temperament() -> Self {
+        Self::default()
+    }
+
+    /// `divisions` equal steps per octave, with A4 still at 440 Hz
+    pub fn edo(divisions: u32) -> Self {
+        Self::new(Scale::equal(divisions), KeyboardMapping::default())
+    }
+
+    /// 5-limit just intonation built on C
+    pub fn just_intonation() -> Self {
+        Self::new(Scale::just_intonation(), KeyboardMapping::default())
+    }
+
+    /// Pythagorean tuning built on C
+    pub fn pythagorean() -> Self {
+        Self::new(Scale::pythagorean(), KeyboardMapping::default())
+    }
+
+    /// retunes the whole scale so `key` plays at `freq` Hz
+    pub fn with_reference(mut self, key: u8, freq: f32) -> Self {
+        self.mapping = self.mapping.with_reference(key, freq);
+        self
+    }
+
+    pub fn scale(&self) -> &Scale {
+        &self.scale
+    }
+
+    pub fn mapping(&self) -> &KeyboardMapping {
+        &self.mapping
+    }
+
+    /// frequency of `key` in Hz, `None` if the key is unmapped or out of the mapped range
+    pub fn key2freq(&self, key: u8) -> Option<f32> {
+        let mapping = &self.mapping;
+        if key < mapping.first_key || key > mapping.last_key {
+            return None;
+        }
+        let degree = mapping.key2degree(key, self.scale.len())?;
+        let reference = mapping.key2degree(mapping.reference_key, self.scale.len()).unwrap_or(0);
+        let cents = self.scale.degree2cents(degree) - self.scale.degree2cents(reference);
+        Some(mapping.reference_freq * 2f64.powf(cents / 1200.) as f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_freq(actual: Option<f32>, expected: f32) {
+        let actual = actual.expect("key should be mapped");
+        assert!((actual - expected).abs() < 0.01, "{actual} Hz, expected {expected}");
+    }
+
+    #[test]
+    fn equal_temperament_matches_midi2freq() {
+        let tuning = Tuning::equal_temperament();
+        for key in [0, 21, 60, 69, 108, 127] {
+            assert_freq(tuning.key2freq(key), midi2freq(key, A4_FREQ));
+        }
+        assert_eq!(freq2midi(261.63, A4_FREQ), 60);
+    }
+
+    #[test]
+    fn reference_moves_the_whole_scale() {
+        let tuning = Tuning::equal_temperament().with_reference(69, 432.);
+        assert_freq(tuning.key2freq(69), 432.);
+        assert_freq(tuning.key2freq(81), 864.);
+        assert_freq(tuning.key2freq(60), 432. * 2f32.powf(-9. / 12.));
+    }
+
+    #[test]
+    fn just_intonation_keeps_pure_ratios_from_c() {
+        let tuning = Tuning::just_intonation().with_reference(60, 264.);
+        assert_freq(tuning.key2freq(64), 330.);
+        assert_freq(tuning.key2freq(67), 396.);
+        assert_freq(tuning.key2freq(72), 528.);
+        assert_freq(tuning.key2freq(55), 198.);
+    }
+
+    #[test]
+    fn edo_divides_the_octave_evenly() {
+        let tuning = Tuning::edo(19).with_reference(60, 256.);
+        assert_freq(tuning.key2freq(79), 512.);
+        assert_freq(tuning.key2freq(41), 128.);
+    }
+
+    #[test]
+    fn mapped_keys_outside_the_range_or_map_are_silent() {
+        let mapping = KeyboardMapping::new(48, 72, 60, 60, 261.63, 0, vec![Some(0), None, Some(2)]);
+        let tuning = Tuning::new(Scale::equal(12), mapping);
+        assert_eq!(tuning.key2freq(47), None);
+        assert_eq!(tuning.key2freq(73), None);
+        assert_eq!(tuning.key2freq(61), None);
+        assert_freq(tuning.key2freq(60), 261.63);
+        // three keys a period, the octave degree defaulting to the 12 of the scale
+        assert_freq(tuning.key2freq(63), 523.26);
+        assert_freq(tuning.key2freq(62), 261.63 * 2f32.powf(2. / 12.));
+    }
+}
@@ -0,0 +1,37 @@
+/// how note velocity maps to loudness, which 1 bit voices play as pulse width
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum VelocityCurve {
+    /// every velocity plays at full loudness
+    Fixed,
+    /// loudness grows in proportion to velocity
+    #[default]
+    Linear,
+    /// loudness grows with velocity raised to a power, 2 being what general midi suggests
+    Exponential(f32),
+    /// loudness at evenly spaced velocities from 0 to 127, in between interpolated
+    Table(Vec<f32>),
+}
+
+impl VelocityCurve {
+
+    /// loudness of a note struck with `velocity`, from 0 to 1
+    pub fn level(&self, velocity: u8) -> f32 {
+        let x = velocity.min(127) as f32 / 127.;
+        let level = match self {
+            VelocityCurve::Fixed => 1.,
+            VelocityCurve::Linear => x,
+            VelocityCurve::Exponential(power) => x.powf(power.max(0.)),
+            VelocityCurve::Table(levels) => match levels.len() {
+                0 => 1.,
+                1 => levels[0],
+                len => {
+                    let pos = x * (len - 1) as f32;
+                    let i = (pos as usize).min(len - 2);
+                    let frac = pos - i as f32;
+                    levels[i] + (levels[i + 1] - levels[i]) * frac
+                }
+            },
+        };
+        level.clamp(0., 1.)
+    }
+}
@@ -1,35 +1,56 @@
-use crate::SAMPLE_RATE;
-use crate::BUFFER_SIZE;
+use crate::config::EngineConfig;
+
+/// one full turn of the phase accumulator
+const PHASE_ONE: f64 = 4_294_967_296.;
 
 /// generates a 1 bit square wave with frequency `freq`
-#[derive(Debug, Default)]
+///
+/// the position within the wave is kept in a 32 bit fixed point phase accumulator,
+/// so `freq` is not limited to periods of a whole number of samples
+#[derive(Debug)]
 pub struct Voice {
-    freq: u32,          // wave's frequency in Hz (musical note played)
+    freq: f32,          // wave's frequency in Hz (musical note played)
     duty: f32,          // wave's duty cycle 
-    counter: u32,       // counts samples per wave period
-    period: u32,        // wave's period in number of samples
-    waveform: u32,      // duty cycle in number of samples
+    phase: u32,         // position within the wave's period, 2^32 being a full period
+    step: u32,          // phase increment per sample
+    waveform: u32,      // duty cycle as a phase threshold
+    pin: Option<f32>,   // width of a pin pulse in samples, replacing the duty cycle
+    sample_rate: u32,   // samples per second the wave is generated at
 }
 
 impl Voice {
 
-    pub fn new(freq: u32, duty: f32) -> Self {
-        let freq = freq;
-        let duty = duty;
-        let counter = 0;
-        let period = SAMPLE_RATE / freq;
-        let waveform = (period as f32 * duty) as u32;
-        Self {
-            freq,
-            duty,
-            counter,
-            period,
-            waveform,
-        }
+    pub fn new(freq: f32, duty: f32, config: &EngineConfig) -> Self {
+        let mut voice = Self {
+            freq: 0.,
+            duty: 0.,
+            phase: 0,
+            step: 0,
+            waveform: 0,
+            pin: None,
+            sample_rate: config.sample_rate,
+        };
+        voice.set(freq, duty);
+        voice
+    }
 
+    fn freq2step(&self, freq: f32) -> u32 {
+        (freq.max(0.) as f64 * PHASE_ONE / self.sample_rate as f64) as u32
     }
 
-    pub fn freq(&self) -> u32 {
+    fn duty2waveform(duty: f32) -> u32 {
+        (duty.clamp(0., 1.) as f64 * PHASE_ONE).min(u32::MAX as f64) as u32
+    }
+
+    /// phase threshold of the wave, a pin pulse never getting past half the period
+    fn update_waveform(&mut self) {
+        self.waveform = match self.pin {
+            Some(width) => (self.step as f64 * width.max(0.) as f64).min(PHASE_ONE / 2.) as u32,
+            None => Self::duty2waveform(self.duty),
+        };
+    }
+
+    pub fn freq(&self) -> f32 {
         self.freq
     }
 
@@ -38,52 +59,58 @@ impl Voice {
     }
 
     /// sets voice's `freq` and `duty` and turns it on
-    pub fn set(&mut self, freq: u32, duty: f32) {
-        if freq == 0 {
-            self.freq = 0;
-            self.period = 0;
-        } else {
-            self.freq = freq;
-            self.duty = duty;
-            self.period = SAMPLE_RATE / freq;
-            self.waveform = (self.period as f32 * duty) as u32;
-        }
+    pub fn set(&mut self, freq: f32, duty: f32) {
+        self.set_freq(freq);
+        self.set_duty(duty);
+    }
+
+    /// changes the frequency without resetting the wave's phase, for detuning and vibrato
+    pub fn set_freq(&mut self, freq: f32) {
+        self.freq = freq;
+        self.step = self.freq2step(freq);
+        self.update_waveform();
+    }
+
+    /// changes the duty cycle without resetting the wave's phase
+    pub fn set_duty(&mut self, duty: f32) {
+        self.duty = duty;
+        self.update_waveform();
+    }
+
+    pub fn pin(&self) -> Option<f32> {
+        self.pin
+    }
+
+    /// plays a pin pulse `width` samples wide each period instead of following the
+    /// duty cycle, whatever the frequency, or goes back to the duty cycle with `None`
+    pub fn set_pin(&mut self, width: Option<f32>) {
+        self.pin = width;
+        self.update_waveform();
+    }
+
+    /// restarts the wave from the beginning of its period
+    pub fn retrigger(&mut self) {
+        self.phase = 0;
     }
 
     /// turns voice off
     pub fn unset(&mut self) {
-        self.freq = 0;
-        self.period = 0;
+        self.freq = 0.;
+        self.step = 0;
     }
 
-    /// returns `BUFFER_SIZE` next samples
-    pub fn out_buffer(&mut self) -> [bool; BUFFER_SIZE] {
-        let mut buffer = [false; BUFFER_SIZE];
-
-        for i in 0..BUFFER_SIZE {
-            self.counter += 1;
-            if self.counter >= self.period {
-                self.counter = 0;
-            } else if self.counter < self.waveform {
-                buffer[i] = true;
-            } else {
-                buffer[i] = false;
-            }
-        }
-
-        buffer
+    /// returns `size` next samples
+    pub fn out_buffer(&mut self, size: usize) -> Vec<bool> {
+        (0..size).map(|_| self.out()).collect()
     }
 
     /// returns next sample
     pub fn out(&mut self) -> bool {
-        self.counter += 1;
-        if self.counter >= self.period {
-            self.counter = 0;
-            false
-        } else if self.counter < self.waveform {
-            true
-        } else {
-            false
+        if self.step == 0 {
+            return false;
         }
+        let out = self.phase < self.waveform;
+        self.phase = self.phase.wrapping_add(self.step);
+        out
     }
-}
\ No newline at end of file
+}
@@ -1,54 +1,45 @@
-use alsa::ValueOr;
-use alsa::pcm::{Access, Format, HwParams, IO};
+use alsa::pcm::IO;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 
-use crate::SAMPLE_RATE;
-use crate::BUFFER_SIZE;
-use crate::AMPLITUDE_MAX;
+use crate::{Error, Result};
 
-pub fn set_pcm_params(pcm: &alsa::PCM) {
-    let hwp = HwParams::any(&pcm).unwrap();
-    hwp.set_channels(1).unwrap();
-    hwp.set_rate(SAMPLE_RATE, ValueOr::Nearest).unwrap();
-    hwp.set_format(Format::U8).unwrap();
-    hwp.set_access(Access::RWInterleaved).unwrap();
-    pcm.hw_params(&hwp).unwrap();
-}
-
-
-pub fn read_message (reader: &mut BufReader<File>) -> Vec<u32>{
+/// reads one `status freq delay_us` line of a text score, empty at the end of the file
+pub fn read_message (reader: &mut BufReader<File>) -> Result<Vec<u32>> {
     let mut message = String::new();
     let mut parts = Vec::new();
-    
-    match reader.read_line(&mut message) {
-        Ok(_) => {
-            for part in message.split_whitespace() {
-                parts.push(part.parse::<u32>().unwrap());
-            }
-            parts
+
+    // blank lines are skipped, only the end of the file reads as nothing
+    while message.trim().is_empty() {
+        message.clear();
+        if reader.read_line(&mut message)? == 0 {
+            return Ok(parts);
         }
-        Err(_) => panic!(), // Handle any errors
     }
-
+    for part in message.split_whitespace() {
+        let value = part.parse::<u32>()
+            .map_err(|_| Error::ScoreParse(format!("'{part}' is not a number in '{}'", message.trim())))?;
+        parts.push(value);
+    }
+    Ok(parts)
 }
 
-pub fn buffer_or(buffer1: [bool; BUFFER_SIZE], buffer2: [bool; BUFFER_SIZE]) -> [bool; BUFFER_SIZE] {
-    let mut buffer = [false; BUFFER_SIZE];
-
-    for i in 0..BUFFER_SIZE {
-        buffer[i] = buffer1[i] | buffer2[i];
+/// ORs `other` into `buffer`
+pub fn buffer_or(buffer: &mut [bool], other: &[bool]) {
+    for (sample, other) in buffer.iter_mut().zip(other) {
+        *sample |= *other;
     }
-
-    buffer
 }
 
-pub fn write_buffer(buffer: [bool; BUFFER_SIZE], io: &IO<u8>) {
-    let mut buffer_out = [0; BUFFER_SIZE];
+/// writes all of `buffer` to `io` as samples `amplitude` high
+///
+/// errors are handed back, so an underrun can be recovered from the way `AlsaSink` does
+pub fn write_buffer(buffer: &[bool], amplitude: u8, io: &IO<u8>) -> Result<()> {
+    let buffer_out: Vec<u8> = buffer.iter().map(|&sample| sample as u8 * amplitude).collect();
 
-    for i in 0..BUFFER_SIZE {
-        buffer_out[i] = buffer[i] as u8 * AMPLITUDE_MAX;
+    let mut written = 0;
+    while written < buffer_out.len() {
+        written += io.writei(&buffer_out[written..])?;
     }
-
-    assert_eq!(io.writei(&buffer_out).unwrap(), BUFFER_SIZE);
+    Ok(())
 } 
\ No newline at end of file